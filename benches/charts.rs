@@ -0,0 +1,50 @@
+use chartsapi_rs::charts::{apply_group_param, build_hashmaps, ChartsHashMaps, GROUP_CODES};
+use chartsapi_rs::config::Config;
+use chartsapi_rs::faa_metafile::{decode_document, StateReader};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const METAFILE: &[u8] = include_bytes!("../tests/fixtures/d-tpp_Metafile.xml");
+
+fn load(config: &Config, metafile: &[u8]) -> ChartsHashMaps {
+    let xml = decode_document(metafile);
+    let (states, dtpp) = StateReader::new(&xml).expect("fixture has a root element");
+    build_hashmaps(config, &dtpp.cycle, states.map(Result::unwrap))
+}
+
+fn parse(c: &mut Criterion) {
+    let config = Config::from_vars(|_| None);
+    c.bench_function("decode_document", |b| {
+        b.iter(|| decode_document(black_box(METAFILE)));
+    });
+    c.bench_function("load", |b| b.iter(|| load(&config, black_box(METAFILE))));
+}
+
+fn lookup(c: &mut Criterion) {
+    let hashmaps = load(&Config::from_vars(|_| None), METAFILE);
+    let mut group = c.benchmark_group("lookup");
+    for ident in ["JFK", "KJFK", "kdfw", "ZZZ"] {
+        group.bench_with_input(BenchmarkId::from_parameter(ident), ident, |b, ident| {
+            b.iter(|| hashmaps.lookup(black_box(ident)));
+        });
+    }
+    group.finish();
+}
+
+fn group_param(c: &mut Criterion) {
+    let charts = load(&Config::from_vars(|_| None), METAFILE)
+        .lookup("JFK")
+        .expect("fixture has JFK");
+    let mut group = c.benchmark_group("apply_group_param");
+    group.bench_function("none", |b| {
+        b.iter(|| apply_group_param(black_box(&charts), None));
+    });
+    for (code, _, _) in GROUP_CODES {
+        group.bench_with_input(BenchmarkId::from_parameter(code), &code, |b, &code| {
+            b.iter(|| apply_group_param(black_box(&charts), Some(code)));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, lookup, group_param);
+criterion_main!(benches);
@@ -0,0 +1,16 @@
+#![no_main]
+
+use chartsapi_rs::charts::build_hashmaps;
+use chartsapi_rs::config::Config;
+use chartsapi_rs::faa_metafile::{decode_document, StateReader};
+use libfuzzer_sys::fuzz_target;
+
+// Any bytes must either load or fail with an error, never panic
+fuzz_target!(|data: &[u8]| {
+    let xml = decode_document(data);
+    let Ok((states, dtpp)) = StateReader::new(&xml) else {
+        return;
+    };
+    let config = Config::from_vars(|_| None);
+    build_hashmaps(&config, &dtpp.cycle, states.map_while(Result::ok));
+});
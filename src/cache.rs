@@ -0,0 +1,47 @@
+use crate::ChartsHashMaps;
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Storage for charts loaded on demand, keyed by cycle. The in-memory implementation is the
+/// default; shared backends such as Redis or Cloudflare KV can implement this trait instead.
+#[async_trait]
+pub trait ChartCache: Send + Sync {
+    async fn get(&self, key: &str) -> Option<Arc<ChartsHashMaps>>;
+    async fn put(&self, key: &str, charts: Arc<ChartsHashMaps>);
+}
+
+/// Keeps the `capacity` most recently used entries in memory
+pub struct InMemoryChartCache {
+    capacity: usize,
+    entries: Mutex<VecDeque<(String, Arc<ChartsHashMaps>)>>,
+}
+
+impl InMemoryChartCache {
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl ChartCache for InMemoryChartCache {
+    async fn get(&self, key: &str) -> Option<Arc<ChartsHashMaps>> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let index = entries.iter().position(|(k, _)| k == key)?;
+        let entry = entries.remove(index)?;
+        let charts = Arc::clone(&entry.1);
+        entries.push_front(entry);
+        drop(entries);
+        Some(charts)
+    }
+
+    async fn put(&self, key: &str, charts: Arc<ChartsHashMaps>) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.retain(|(k, _)| k != key);
+        entries.push_front((key.to_string(), charts));
+        entries.truncate(self.capacity);
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Aliases used when no file is configured
+const DEFAULT_ALIASES: &str = include_str!("chart_aliases.json");
+
+/// Pilot shorthand that doesn't literally appear in chart names, such as "the visual" or "cir",
+/// mapped to the chart name fragments it stands for. Read from JSON like
+/// `{"the visual": ["VISUAL"], "cir": ["-A", "-B"]}`, so terms can be added without code changes.
+pub struct ChartAliases {
+    aliases: HashMap<String, Vec<String>>,
+}
+
+impl ChartAliases {
+    pub fn from_json(json: &[u8]) -> Result<Self, serde_json::Error> {
+        let aliases: HashMap<String, Vec<String>> = serde_json::from_slice(json)?;
+        Ok(Self {
+            aliases: aliases
+                .into_iter()
+                .map(|(term, patterns)| {
+                    (
+                        normalize(&term),
+                        patterns.iter().map(|pattern| normalize(pattern)).collect(),
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        Ok(Self::from_json(&std::fs::read(path)?)?)
+    }
+
+    pub fn alias_count(&self) -> usize {
+        self.aliases.len()
+    }
+
+    /// Chart name fragments the search term stands for, ignoring casing and spacing
+    pub fn patterns(&self, term: &str) -> Option<&[String]> {
+        self.aliases.get(&normalize(term)).map(Vec::as_slice)
+    }
+}
+
+impl Default for ChartAliases {
+    fn default() -> Self {
+        Self::from_json(DEFAULT_ALIASES.as_bytes()).expect("Default chart aliases are valid JSON")
+    }
+}
+
+fn normalize(term: &str) -> String {
+    term.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_uppercase()
+}
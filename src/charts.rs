@@ -0,0 +1,584 @@
+use crate::config::Config;
+use crate::faa_metafile;
+use crate::response_dtos::ResponseDto::{Charts, GroupedCharts};
+use crate::response_dtos::{
+    AirportCountsDto, ChartCodeDto, ChartCountsDto, ChartDto, ChartGroup, GroupedChartsDto,
+    LookupPath, PaginationDto, ResponseDto,
+};
+use indexmap::IndexMap;
+use metrics::counter;
+use std::collections::HashSet;
+use tracing::{debug, info, warn};
+
+/// Lookup maps for one cycle.
+///
+/// The FAA map is deliberately not sharded by state: a lookup only has the ident, so shards would
+/// need an extra ident-to-state index and two hash lookups instead of one, and loading is dominated
+/// by parsing the metafile rather than inserting into the map. Whole cycles are swapped at once, so
+/// per-state invalidation would not be used either.
+pub struct ChartsHashMaps {
+    pub faa: IndexMap<String, Vec<ChartDto>>,
+    pub icao: IndexMap<String, String>,
+    pub counts: ChartCountsDto,
+    pub airport_counts: AirportCountsDto,
+    /// Distinct raw chart codes with their group and count, in the order first seen
+    pub codes: Vec<ChartCodeDto>,
+    /// Charts the metafile marks as deleted this cycle, keyed by FAA ident, kept only to tell
+    /// searches for them apart from searches for charts that never existed
+    pub deleted: IndexMap<String, Vec<ChartDto>>,
+}
+
+impl ChartsHashMaps {
+    #[must_use]
+    pub fn lookup(&self, apt_id: &str) -> Option<Vec<ChartDto>> {
+        self.faa_ident(apt_id)
+            .and_then(|faa_id| self.faa.get(faa_id).cloned())
+    }
+
+    /// How `lookup` resolves an ident, for diagnostics
+    #[must_use]
+    pub fn lookup_path(&self, apt_id: &str) -> LookupPath {
+        if self.faa.contains_key(apt_id) {
+            LookupPath::Faa
+        } else if self.icao.contains_key(&apt_id.to_uppercase()) {
+            LookupPath::Icao
+        } else {
+            LookupPath::NotFound
+        }
+    }
+
+    /// Canonical FAA ident for an FAA or ICAO ident
+    #[must_use]
+    pub fn faa_ident(&self, apt_id: &str) -> Option<&str> {
+        self.faa.get_key_value(apt_id).map_or_else(
+            || self.icao.get(&apt_id.to_uppercase()).map(String::as_str),
+            |(faa_id, _)| Some(faa_id.as_str()),
+        )
+    }
+
+    /// Charts of the airport deleted this cycle, which may be all it had
+    pub fn lookup_deleted(&self, apt_id: &str) -> Option<&[ChartDto]> {
+        self.deleted
+            .get(self.faa_ident(apt_id).unwrap_or(apt_id))
+            .map(Vec::as_slice)
+    }
+}
+
+pub const GROUP_1_TYPES: [ChartGroup; 5] = [
+    ChartGroup::Apd,
+    ChartGroup::General,
+    ChartGroup::Departures,
+    ChartGroup::Arrivals,
+    ChartGroup::Approaches,
+];
+pub const GROUP_2_TYPES: [ChartGroup; 1] = [ChartGroup::Apd];
+pub const GROUP_3_TYPES: [ChartGroup; 2] = [ChartGroup::Apd, ChartGroup::General];
+pub const GROUP_4_TYPES: [ChartGroup; 1] = [ChartGroup::Departures];
+pub const GROUP_5_TYPES: [ChartGroup; 1] = [ChartGroup::Arrivals];
+pub const GROUP_6_TYPES: [ChartGroup; 1] = [ChartGroup::Approaches];
+pub const GROUP_7_TYPES: [ChartGroup; 3] = [
+    ChartGroup::Departures,
+    ChartGroup::Arrivals,
+    ChartGroup::Approaches,
+];
+
+/// How a `group` code lays out the charts it includes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupOutput {
+    Flat,
+    /// Grouped under the upstream keys, with airport diagrams in `General`
+    Grouped,
+    /// Grouped with a bucket per [`ChartGroup`], airport diagrams in their own `APD`
+    ByChartGroup,
+}
+
+/// Every `group` code with the chart groups it includes and how it lays out its output
+pub const GROUP_CODES: [(i32, &[ChartGroup], GroupOutput); 8] = [
+    (0, &GROUP_1_TYPES, GroupOutput::ByChartGroup),
+    (1, &GROUP_1_TYPES, GroupOutput::Grouped),
+    (2, &GROUP_2_TYPES, GroupOutput::Flat),
+    (3, &GROUP_3_TYPES, GroupOutput::Flat),
+    (4, &GROUP_4_TYPES, GroupOutput::Flat),
+    (5, &GROUP_5_TYPES, GroupOutput::Flat),
+    (6, &GROUP_6_TYPES, GroupOutput::Flat),
+    (7, &GROUP_7_TYPES, GroupOutput::Grouped),
+];
+
+/// Chart groups included by a `group` code, ignoring whether it groups its output
+#[must_use]
+pub fn group_types(group: i32) -> Option<&'static [ChartGroup]> {
+    GROUP_CODES
+        .iter()
+        .find(|(code, _, _)| *code == group)
+        .map(|(_, types, _)| *types)
+}
+
+/// Whether a `group` code keeps airport diagrams apart from `General` when grouping
+#[must_use]
+pub fn splits_apd(group: Option<i32>) -> bool {
+    group.is_some_and(|group| {
+        GROUP_CODES
+            .iter()
+            .any(|(code, _, output)| *code == group && *output == GroupOutput::ByChartGroup)
+    })
+}
+
+#[must_use]
+pub fn apply_group_param(charts: &[ChartDto], group: Option<i32>) -> ResponseDto {
+    group.map_or_else(
+        || Charts(charts.to_owned()),
+        |i| {
+            GROUP_CODES
+                .iter()
+                .find(|(code, _, _)| *code == i)
+                .map_or_else(
+                    || Charts(vec![]),
+                    |(_, types, output)| filter_group_by_types(charts, types, *output),
+                )
+        },
+    )
+}
+
+#[must_use]
+pub fn filter_group_by_types(
+    charts: &[ChartDto],
+    types: &[ChartGroup],
+    output: GroupOutput,
+) -> ResponseDto {
+    let charts = charts
+        .iter()
+        .filter(|c| types.contains(&c.chart_group))
+        .cloned();
+    match output {
+        GroupOutput::Flat => Charts(charts.collect()),
+        GroupOutput::Grouped => {
+            let mut grouped = GroupedChartsDto::new();
+            charts.for_each(|c| grouped.add_chart(c));
+            GroupedCharts(grouped)
+        }
+        GroupOutput::ByChartGroup => {
+            let mut grouped = GroupedChartsDto::new();
+            charts.for_each(|c| grouped.add_chart_by_group(c));
+            GroupedCharts(grouped)
+        }
+    }
+}
+
+pub fn build_hashmaps(
+    config: &Config,
+    current_cycle: &str,
+    states: impl IntoIterator<Item = faa_metafile::State>,
+) -> ChartsHashMaps {
+    let base_url = pdf_base_url(config, current_cycle);
+    let mut faa: IndexMap<String, Vec<ChartDto>> = IndexMap::new();
+    let mut icao: IndexMap<String, String> = IndexMap::new();
+    let mut counts = ChartCountsDto::default();
+    let mut codes: IndexMap<String, ChartCodeDto> = IndexMap::new();
+    let mut deleted: IndexMap<String, Vec<ChartDto>> = IndexMap::new();
+    let mut excluded = 0;
+    let mut without_pdf = 0;
+    let mut unknown_states = 0;
+    let mut icao_collisions = HashSet::new();
+
+    // Records are taken out of their airport, city and state so each chart can be built from
+    // the fields they hold
+    for mut state in states {
+        // Kept rather than failing the load, as the charts are still usable
+        if !state.has_known_code() {
+            warn!(
+                "Unknown state code {:?} ({}) in cycle {current_cycle}, serving its charts anyway",
+                state.id, state.full_name
+            );
+            counter!("chartsapi_unknown_state_codes_total", "code" => state.id.clone())
+                .increment(1);
+            unknown_states += 1;
+        }
+        for mut city in std::mem::take(&mut state.cities) {
+            for mut airport in std::mem::take(&mut city.airports) {
+                for record in std::mem::take(&mut airport.chart_records) {
+                    if record.useraction == "D" {
+                        if !config.exclude_codes.contains(&record.chart_code) {
+                            deleted.entry(airport.apt_ident.clone()).or_default().push(
+                                chart_from_record(&state, &city, &airport, record, &base_url),
+                            );
+                        }
+                        continue;
+                    }
+                    if config.exclude_codes.contains(&record.chart_code) {
+                        excluded += 1;
+                        continue;
+                    }
+                    // Records without a PDF, such as some continuation pages, would link to
+                    // the bare cycle directory
+                    if record.pdf_name.trim().is_empty() {
+                        debug!(
+                            "Skipping chart {:?} of {} without a PDF",
+                            record.chart_name, airport.apt_ident
+                        );
+                        without_pdf += 1;
+                        continue;
+                    }
+
+                    let chart_dto = chart_from_record(&state, &city, &airport, record, &base_url);
+
+                    counts.add_chart(&chart_dto);
+                    count_chart_code(&mut codes, &chart_dto, current_cycle);
+
+                    // First airport wins an ICAO ident shared by several, which is a data error.
+                    // Mappings are only added alongside a chart of the airport, so an ICAO ident
+                    // can't map to an airport without charts that its lookups would miss.
+                    if !airport.icao_ident.is_empty() {
+                        let faa_id = icao
+                            .entry(airport.icao_ident.clone())
+                            .or_insert_with(|| chart_dto.faa_ident.clone());
+                        if *faa_id != chart_dto.faa_ident
+                            && icao_collisions
+                                .insert((airport.icao_ident.clone(), chart_dto.faa_ident.clone()))
+                        {
+                            warn!(
+                                "ICAO ident {} of {} already maps to {}, keeping the first mapping",
+                                airport.icao_ident, chart_dto.faa_ident, faa_id
+                            );
+                        }
+                    }
+
+                    // Prefer the syntax below, but requires a clone in the modify case
+                    // faa.entry(chart_dto.faa_ident.clone())
+                    //     .and_modify(|charts| charts.push(chart_dto.clone()))
+                    //     .or_insert(vec![chart_dto]);
+
+                    if let Some(charts) = faa.get_mut(&chart_dto.faa_ident) {
+                        charts.push(chart_dto);
+                    } else {
+                        faa.insert(chart_dto.faa_ident.clone(), vec![chart_dto]);
+                    }
+                }
+            }
+        }
+    }
+
+    info!("Loaded {} charts", counts.total);
+    if excluded > 0 {
+        info!("Excluded {excluded} charts by chart code");
+    }
+    if without_pdf > 0 {
+        warn!("Skipped {without_pdf} charts without a PDF name");
+    }
+    if unknown_states > 0 {
+        warn!("Found {unknown_states} unknown state codes");
+    }
+
+    let airport_counts = count_airports(&faa);
+
+    ChartsHashMaps {
+        faa,
+        icao,
+        counts,
+        airport_counts,
+        codes: codes.into_values().collect(),
+        deleted,
+    }
+}
+
+fn chart_from_record(
+    state: &faa_metafile::State,
+    city: &faa_metafile::City,
+    airport: &faa_metafile::Airport,
+    record: faa_metafile::ChartRecord,
+    base_url: &str,
+) -> ChartDto {
+    ChartDto {
+        state: state.id.clone(),
+        state_full: state.full_name.clone(),
+        city: city.id.clone(),
+        volume: city.volume.clone(),
+        airport_name: airport.id.clone(),
+        military: airport.military.clone(),
+        faa_ident: airport.apt_ident.clone(),
+        alnum: airport.alnum.clone(),
+        icao_ident: Some(airport.icao_ident.clone()),
+        chart_seq: record.chartseq,
+        chart_name: record.chart_name,
+        pdf_path: format!("{base_url}/{pdf}", pdf = record.pdf_name),
+        chart_group: chart_group_for_code(&record.chart_code).unwrap_or(ChartGroup::General),
+        chart_code: record.chart_code,
+        pdf_name: record.pdf_name,
+        amendment_number: record.amdtnum,
+        amendment_date: record.amdtdate,
+        pagination: PaginationDto {
+            change_notice_section: record.cnsection,
+            change_notice_page: record.cnpage,
+            bound_volume_section: record.bvsection,
+            bound_volume_page: record.bvpage,
+        },
+        links: None,
+    }
+}
+
+/// Airport-level counts, taken from each airport's first chart as they share airport fields
+fn count_airports(faa: &IndexMap<String, Vec<ChartDto>>) -> AirportCountsDto {
+    let airports = faa.values().filter_map(|charts| charts.first());
+    AirportCountsDto {
+        total: faa.len(),
+        military: airports.clone().filter(|c| c.military == "Y").count(),
+        with_icao: airports
+            .filter(|c| c.icao_ident.as_deref().is_some_and(|icao| !icao.is_empty()))
+            .count(),
+    }
+}
+
+fn count_chart_code(
+    codes: &mut IndexMap<String, ChartCodeDto>,
+    chart_dto: &ChartDto,
+    current_cycle: &str,
+) {
+    codes
+        .entry(chart_dto.chart_code.clone())
+        .or_insert_with(|| {
+            // New FAA chart types would otherwise be served as General unnoticed
+            if chart_group_for_code(&chart_dto.chart_code).is_none() {
+                warn!(
+                    "Unrecognized chart code {:?} in cycle {current_cycle}, serving it as General",
+                    chart_dto.chart_code
+                );
+                counter!("chartsapi_unknown_chart_codes_total", "code" => chart_dto.chart_code.clone())
+                    .increment(1);
+            }
+            ChartCodeDto {
+                code: chart_dto.chart_code.clone(),
+                group: chart_dto.chart_group.clone(),
+                count: 0,
+            }
+        })
+        .count += 1;
+}
+
+/// Group for a known FAA chart code, or `None` for codes this API doesn't know yet
+fn chart_group_for_code(chart_code: &str) -> Option<ChartGroup> {
+    match chart_code {
+        "IAP" => Some(ChartGroup::Approaches),
+        "ODP" | "DP" | "DAU" => Some(ChartGroup::Departures),
+        "STAR" => Some(ChartGroup::Arrivals),
+        "APD" => Some(ChartGroup::Apd),
+        "MIN" | "LAH" | "HOT" => Some(ChartGroup::General),
+        _ => None,
+    }
+}
+
+/// Base URL for chart PDFs, pointing at a mirror when configured while keeping the cycle segment
+fn pdf_base_url(config: &Config, current_cycle: &str) -> String {
+    config.pdf_base_url.as_ref().map_or_else(
+        || cycle_url(config, current_cycle),
+        |base_url| format!("{}/{current_cycle}", base_url.trim_end_matches('/')),
+    )
+}
+
+/// Where the FAA publishes a cycle's metafile and PDFs
+#[must_use]
+pub fn cycle_url(config: &Config, current_cycle: &str) -> String {
+    format!("{}/{current_cycle}", config.faa_base_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::faa_metafile::{decode_document, StateReader};
+    use crate::response_dtos::tests::chart;
+    use proptest::prelude::*;
+
+    const METAFILE: &[u8] = include_bytes!("../tests/fixtures/d-tpp_Metafile.xml");
+
+    fn load(config: &Config, xml: &str) -> ChartsHashMaps {
+        let (states, dtpp) = StateReader::new(xml).unwrap();
+        build_hashmaps(config, &dtpp.cycle, states.map(Result::unwrap))
+    }
+
+    /// An airport whose records are all skipped, as deleted, excluded or without a PDF
+    const WITHOUT_CHARTS: &str = r#"<digital_tpp cycle="2410" from_edate="0901Z  10/03/24" to_edate="0901Z  10/31/24">
+<state_code ID="NY" state_fullname="New York"><city_name ID="NOWHERE" volume="NE-2">
+<airport_name ID="NO CHARTS" military="N" apt_ident="ZZZ" icao_ident="KZZZ" alnum="1">
+<record><chartseq>10100</chartseq><chart_code>HOT</chart_code><chart_name>HOT SPOT</chart_name><useraction/><pdf_name>ZZZHOT.PDF</pdf_name><cn_flg>N</cn_flg><cnsection/><cnpage/><bvsection>C</bvsection><bvpage/><procuid/><two_colored>N</two_colored><civil/><faanfd18/><copter/><amdtnum/><amdtdate/></record>
+<record><chartseq>50100</chartseq><chart_code>IAP</chart_code><chart_name>VOR RWY 1</chart_name><useraction>D</useraction><pdf_name>ZZZV1.PDF</pdf_name><cn_flg>N</cn_flg><cnsection/><cnpage/><bvsection>C</bvsection><bvpage/><procuid/><two_colored>N</two_colored><civil/><faanfd18/><copter/><amdtnum/><amdtdate/></record>
+<record><chartseq>50200</chartseq><chart_code>IAP</chart_code><chart_name>CONTINUED</chart_name><useraction/><pdf_name/><cn_flg>N</cn_flg><cnsection/><cnpage/><bvsection>C</bvsection><bvpage/><procuid/><two_colored>N</two_colored><civil/><faanfd18/><copter/><amdtnum/><amdtdate/></record>
+</airport_name></city_name></state_code></digital_tpp>"#;
+
+    #[test]
+    fn icao_idents_only_map_to_airports_with_charts() {
+        let config =
+            Config::from_vars(|key| (key == "CHARTSAPI_EXCLUDE_CODES").then(|| "HOT".to_string()));
+        for xml in [decode_document(METAFILE), WITHOUT_CHARTS.to_string()] {
+            let hashmaps = load(&config, &xml);
+            for (icao_id, faa_id) in &hashmaps.icao {
+                assert!(
+                    hashmaps.faa.contains_key(faa_id),
+                    "{icao_id} maps to {faa_id}"
+                );
+            }
+        }
+        let hashmaps = load(&config, WITHOUT_CHARTS);
+        assert!(hashmaps.faa.is_empty());
+        assert!(hashmaps.icao.is_empty());
+        assert!(hashmaps.lookup("KZZZ").is_none());
+        assert!(hashmaps.lookup_deleted("ZZZ").is_some());
+    }
+
+    #[test]
+    fn first_airport_keeps_a_shared_icao_ident() {
+        let xml = decode_document(METAFILE).replace(r#"icao_ident="KLGA""#, r#"icao_ident="KJFK""#);
+        let hashmaps = load(&Config::from_vars(|_| None), &xml);
+        assert_eq!(hashmaps.faa_ident("KJFK"), Some("JFK"));
+        assert_eq!(hashmaps.lookup("KJFK").unwrap()[0].faa_ident, "JFK");
+        assert_eq!(hashmaps.faa_ident("KLGA"), None);
+        // The later airport stays reachable by its FAA ident
+        assert_eq!(hashmaps.lookup("LGA").unwrap()[0].faa_ident, "LGA");
+    }
+
+    #[test]
+    fn pdf_paths_keep_the_cycle_under_an_overridden_base_url() {
+        let xml = decode_document(METAFILE);
+        let faa = load(&Config::from_vars(|_| None), &xml);
+        assert_eq!(
+            faa.lookup("JFK").unwrap()[0].pdf_path,
+            "https://aeronav.faa.gov/d-tpp/2410/NE2TO.PDF"
+        );
+        for base_url in [
+            "https://charts.example.com/d-tpp",
+            "https://charts.example.com/d-tpp/",
+        ] {
+            let config = Config::from_vars(|key| {
+                (key == "CHARTSAPI_PDF_BASE_URL").then(|| base_url.to_string())
+            });
+            assert_eq!(
+                load(&config, &xml).lookup("JFK").unwrap()[0].pdf_path,
+                "https://charts.example.com/d-tpp/2410/NE2TO.PDF",
+                "{base_url}"
+            );
+        }
+    }
+
+    fn charts() -> impl Strategy<Value = Vec<ChartDto>> {
+        prop::collection::vec(
+            prop_oneof![
+                Just(ChartGroup::General),
+                Just(ChartGroup::Departures),
+                Just(ChartGroup::Arrivals),
+                Just(ChartGroup::Approaches),
+                Just(ChartGroup::Apd),
+            ],
+            0..40,
+        )
+        .prop_map(|groups| {
+            groups
+                .into_iter()
+                .enumerate()
+                .map(|(index, group)| chart(index, group))
+                .collect()
+        })
+    }
+
+    fn names(charts: &[ChartDto]) -> Vec<String> {
+        let mut names: Vec<_> = charts.iter().map(|c| c.pdf_name.clone()).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// The groups each bucket of grouped output may hold
+    fn bucket_groups(
+        grouped: &GroupedChartsDto,
+        output: GroupOutput,
+    ) -> [(&Option<Vec<ChartDto>>, Vec<ChartGroup>); 5] {
+        let general = if output == GroupOutput::ByChartGroup {
+            vec![ChartGroup::General]
+        } else {
+            vec![ChartGroup::General, ChartGroup::Apd]
+        };
+        [
+            (&grouped.general, general),
+            (&grouped.departures, vec![ChartGroup::Departures]),
+            (&grouped.arrivals, vec![ChartGroup::Arrivals]),
+            (&grouped.approaches, vec![ChartGroup::Approaches]),
+            (&grouped.apd, vec![ChartGroup::Apd]),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn every_relevant_chart_appears_exactly_once(charts in charts()) {
+            prop_assert_eq!(
+                names(&apply_group_param(&charts, None).into_charts()),
+                names(&charts)
+            );
+            for (code, types, _) in GROUP_CODES {
+                let relevant: Vec<_> = charts
+                    .iter()
+                    .filter(|c| types.contains(&c.chart_group))
+                    .cloned()
+                    .collect();
+                let output = apply_group_param(&charts, Some(code)).into_charts();
+                prop_assert_eq!(names(&output), names(&relevant), "group {}", code);
+            }
+        }
+
+        #[test]
+        fn no_chart_lands_in_the_wrong_bucket(charts in charts()) {
+            for (code, _, output) in GROUP_CODES {
+                let GroupedCharts(grouped) = apply_group_param(&charts, Some(code)) else {
+                    prop_assert_eq!(output, GroupOutput::Flat, "group {}", code);
+                    continue;
+                };
+                if output == GroupOutput::Grouped {
+                    prop_assert!(grouped.apd.is_none(), "group {}", code);
+                }
+                for (bucket, groups) in bucket_groups(&grouped, output) {
+                    for chart in bucket.iter().flatten() {
+                        prop_assert!(
+                            groups.contains(&chart.chart_group),
+                            "group {}: {:?} in a bucket for {:?}",
+                            code,
+                            chart.chart_group,
+                            groups
+                        );
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn flat_and_grouped_output_hold_the_same_charts(charts in charts()) {
+            for (code, types, _) in GROUP_CODES {
+                for output in [GroupOutput::Grouped, GroupOutput::ByChartGroup] {
+                    let flat = filter_group_by_types(&charts, types, GroupOutput::Flat);
+                    let grouped = filter_group_by_types(&charts, types, output);
+                    prop_assert_eq!(
+                        names(&flat.into_charts()),
+                        names(&grouped.into_charts()),
+                        "group {}", code
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn grouped_output_keeps_the_flat_order(charts in charts()) {
+            for (code, types, output) in GROUP_CODES {
+                let flat: Vec<_> = filter_group_by_types(&charts, types, GroupOutput::Flat)
+                    .into_charts()
+                    .into_iter()
+                    .map(|c| c.pdf_name)
+                    .collect();
+                let GroupedCharts(grouped) = filter_group_by_types(&charts, types, output) else {
+                    continue;
+                };
+                for bucket in grouped.groups().into_iter().flatten() {
+                    let bucket: Vec<_> = bucket.iter().map(|c| c.pdf_name.clone()).collect();
+                    let in_flat_order: Vec<_> = flat
+                        .iter()
+                        .filter(|name| bucket.contains(name))
+                        .cloned()
+                        .collect();
+                    prop_assert_eq!(&bucket, &in_flat_order, "group {}", code);
+                }
+            }
+        }
+    }
+}
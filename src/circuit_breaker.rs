@@ -0,0 +1,89 @@
+use metrics::gauge;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Stops calls to a failing upstream after `failure_threshold` consecutive failures. Once
+/// `cooldown` has passed a single probe call is let through, closing the breaker on success and
+/// reopening it on failure. The state is exported as `chartsapi_circuit_state` (0 closed,
+/// 1 half-open, 2 open).
+pub struct CircuitBreaker {
+    name: &'static str,
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Clone, Copy)]
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { since: Instant },
+    // A probe that never reports back, e.g. a cancelled request, is retried after the cooldown
+    HalfOpen { since: Instant },
+}
+
+impl CircuitBreaker {
+    pub const fn new(name: &'static str, failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            name,
+            failure_threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a call may be made now, moving an open breaker to half-open once it cooled down
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { since } | BreakerState::HalfOpen { since }
+                if since.elapsed() >= self.cooldown =>
+            {
+                *state = BreakerState::HalfOpen {
+                    since: Instant::now(),
+                };
+                drop(state);
+                self.record_state(1.0);
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => false,
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let was_closed = matches!(*state, BreakerState::Closed { .. });
+        *state = BreakerState::Closed { failures: 0 };
+        drop(state);
+        if !was_closed {
+            tracing::info!("Circuit breaker for {} closed", self.name);
+            self.record_state(0.0);
+        }
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let failures = match *state {
+            BreakerState::Closed { failures } => failures + 1,
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => self.failure_threshold,
+        };
+        if failures < self.failure_threshold {
+            *state = BreakerState::Closed { failures };
+            return;
+        }
+        *state = BreakerState::Open {
+            since: Instant::now(),
+        };
+        drop(state);
+        tracing::warn!(
+            "Circuit breaker for {} opened, pausing calls for {:?}",
+            self.name,
+            self.cooldown
+        );
+        self.record_state(2.0);
+    }
+
+    fn record_state(&self, value: f64) {
+        gauge!("chartsapi_circuit_state", "upstream" => self.name).set(value);
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+use tracing::level_filters::LevelFilter;
+use tracing::{info, warn};
+
+#[derive(Clone)]
+pub struct Config {
+    pub assets_dir: PathBuf,
+    pub pdf_dir: Option<PathBuf>,
+    pub pdf_base_url: Option<String>,
+    pub faa_base_url: String,
+    pub faa_info_url: String,
+    pub base_path: String,
+    pub nest_base_path: bool,
+    pub trust_forwarded_prefix: bool,
+    pub exclude_codes: Vec<String>,
+    pub hot_airports: Vec<String>,
+    pub airport_info_file: Option<PathBuf>,
+    pub chart_aliases_file: Option<PathBuf>,
+    pub integrity_check_interval: Option<Duration>,
+    pub cycle_max_age: Duration,
+    pub charts_max_age: Duration,
+    pub groups_max_age: Duration,
+    pub min_charts: usize,
+    pub expiry_grace: Duration,
+    pub worker_threads: Option<usize>,
+    pub admin_token: Option<String>,
+    pub fetch_timeout: Duration,
+    pub max_in_flight: usize,
+    pub refresh_interval: Duration,
+    pub refresh_jitter: Duration,
+    pub log_level: LevelFilter,
+    pub cors_origins: Vec<String>,
+}
+
+impl Config {
+    /// Reads the environment, overridden by the `KEY=value` lines of the file at
+    /// `CHARTSAPI_CONFIG_FILE` if set. The environment is fixed once started, so reloads only pick
+    /// up changes made to that file.
+    #[must_use]
+    pub fn load() -> Self {
+        let file = env::var("CHARTSAPI_CONFIG_FILE")
+            .map(|path| read_config_file(&path))
+            .unwrap_or_default();
+        Self::from_vars(|key| file.get(key).cloned().or_else(|| env::var(key).ok()))
+    }
+
+    /// Builds the config from `var`, which looks up each setting by its environment key
+    #[must_use]
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let secs = |key: &str, default_secs: u64| {
+            Duration::from_secs(
+                var(key)
+                    .and_then(|secs| secs.parse().ok())
+                    .unwrap_or(default_secs),
+            )
+        };
+        Self {
+            assets_dir: var("CHARTSAPI_ASSETS_DIR")
+                .map_or_else(|| PathBuf::from("assets"), PathBuf::from),
+            pdf_dir: var("CHARTSAPI_PDF_DIR").map(PathBuf::from),
+            pdf_base_url: var("CHARTSAPI_PDF_BASE_URL"),
+            faa_base_url: var("CHARTSAPI_FAA_BASE_URL").map_or_else(
+                || "https://aeronav.faa.gov/d-tpp".to_string(),
+                |url| url.trim_end_matches('/').to_string(),
+            ),
+            faa_info_url: var("CHARTSAPI_FAA_INFO_URL")
+                .unwrap_or_else(|| "https://external-api.faa.gov/apra/dtpp/info".to_string()),
+            // Normalized to a leading slash without a trailing one, or empty for the root
+            base_path: var("CHARTSAPI_BASE_PATH")
+                .map(|path| path.trim().trim_matches('/').to_string())
+                .filter(|path| !path.is_empty())
+                .map(|path| format!("/{path}"))
+                .unwrap_or_default(),
+            nest_base_path: var("CHARTSAPI_NEST_BASE_PATH").is_some_and(|nest| nest == "true"),
+            trust_forwarded_prefix: var("CHARTSAPI_TRUST_FORWARDED_PREFIX")
+                .is_some_and(|trust| trust == "true"),
+            exclude_codes: var("CHARTSAPI_EXCLUDE_CODES")
+                .map(|codes| {
+                    codes
+                        .split(',')
+                        .map(|code| code.trim().to_uppercase())
+                        .filter(|code| !code.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            hot_airports: var("CHARTSAPI_HOT_AIRPORTS")
+                .map(|idents| {
+                    idents
+                        .split(',')
+                        .map(|ident| ident.trim().to_uppercase())
+                        .filter(|ident| !ident.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            airport_info_file: var("CHARTSAPI_AIRPORT_INFO_FILE").map(PathBuf::from),
+            chart_aliases_file: var("CHARTSAPI_CHART_ALIASES_FILE").map(PathBuf::from),
+            integrity_check_interval: var("CHARTSAPI_INTEGRITY_CHECK_SECS")
+                .and_then(|secs| secs.parse().ok())
+                .map(Duration::from_secs),
+            cycle_max_age: secs("CHARTSAPI_CYCLE_MAX_AGE_SECS", 300),
+            charts_max_age: secs("CHARTSAPI_CHARTS_MAX_AGE_SECS", 3600),
+            groups_max_age: secs("CHARTSAPI_GROUPS_MAX_AGE_SECS", 86400),
+            min_charts: var("CHARTSAPI_MIN_CHARTS")
+                .and_then(|count| count.parse().ok())
+                .unwrap_or(1),
+            expiry_grace: secs("CHARTSAPI_EXPIRY_GRACE_SECS", 86400),
+            worker_threads: var("CHARTSAPI_WORKER_THREADS")
+                .and_then(|threads| threads.parse().ok())
+                .filter(|threads| *threads > 0),
+            admin_token: var("CHARTSAPI_ADMIN_TOKEN").filter(|token| !token.is_empty()),
+            fetch_timeout: secs("CHARTSAPI_FETCH_TIMEOUT_SECS", 60),
+            max_in_flight: var("CHARTSAPI_MAX_IN_FLIGHT")
+                .and_then(|count| count.parse().ok())
+                .filter(|count| *count > 0)
+                .unwrap_or(1024),
+            refresh_interval: secs("CHARTSAPI_REFRESH_INTERVAL_SECS", 3600),
+            refresh_jitter: secs("CHARTSAPI_REFRESH_JITTER_SECS", 300),
+            log_level: var("CHARTSAPI_LOG_LEVEL")
+                .and_then(|level| level.parse().ok())
+                .unwrap_or(LevelFilter::DEBUG),
+            cors_origins: var("CHARTSAPI_CORS_ORIGINS")
+                .map(|origins| {
+                    origins
+                        .split(',')
+                        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                        .filter(|origin| !origin.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Parses `KEY=value` lines, skipping blank lines and `#` comments. An unreadable file is
+/// treated as empty so a bad reload can't take the config down with it.
+fn read_config_file(path: &str) -> HashMap<String, String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!("Could not read config file {path}: {e}");
+            return HashMap::new();
+        }
+    };
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// The config shared by the server and background tasks, replaced as a whole on reload. Callers
+/// take a snapshot with [`Self::get`] for each operation so it sees consistent settings.
+#[derive(Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        Arc::clone(&self.0.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Applies the settings of `reloaded` that are read afresh wherever they're used, logging each
+    /// change. Changes to the others, which are baked into the router or background tasks at
+    /// startup, are only logged as needing a restart.
+    pub fn reload(&self, reloaded: Config) -> Arc<Config> {
+        let current = self.get();
+        warn_restart_only(&current, &reloaded);
+        let mut updated = (*current).clone();
+        apply(
+            "hot airports",
+            &mut updated.hot_airports,
+            reloaded.hot_airports,
+        );
+        apply(
+            "expiry grace",
+            &mut updated.expiry_grace,
+            reloaded.expiry_grace,
+        );
+        apply(
+            "refresh interval",
+            &mut updated.refresh_interval,
+            reloaded.refresh_interval,
+        );
+        apply(
+            "refresh jitter",
+            &mut updated.refresh_jitter,
+            reloaded.refresh_jitter,
+        );
+        apply("log level", &mut updated.log_level, reloaded.log_level);
+        apply(
+            "CORS origins",
+            &mut updated.cors_origins,
+            reloaded.cors_origins,
+        );
+
+        let updated = Arc::new(updated);
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = Arc::clone(&updated);
+        updated
+    }
+}
+
+/// Logs the changed settings that are baked in at startup, which `reload` doesn't apply
+fn warn_restart_only(current: &Config, reloaded: &Config) {
+    // Only used while loading a metafile, which is skipped until the FAA changes it
+    restart_only(
+        "exclude codes",
+        &current.exclude_codes,
+        &reloaded.exclude_codes,
+    );
+    restart_only("min charts", &current.min_charts, &reloaded.min_charts);
+    restart_only("assets dir", &current.assets_dir, &reloaded.assets_dir);
+    restart_only("PDF dir", &current.pdf_dir, &reloaded.pdf_dir);
+    restart_only(
+        "PDF base URL",
+        &current.pdf_base_url,
+        &reloaded.pdf_base_url,
+    );
+    restart_only(
+        "FAA base URL",
+        &current.faa_base_url,
+        &reloaded.faa_base_url,
+    );
+    restart_only(
+        "FAA info URL",
+        &current.faa_info_url,
+        &reloaded.faa_info_url,
+    );
+    restart_only("base path", &current.base_path, &reloaded.base_path);
+    restart_only(
+        "nest base path",
+        &current.nest_base_path,
+        &reloaded.nest_base_path,
+    );
+    restart_only(
+        "trust forwarded prefix",
+        &current.trust_forwarded_prefix,
+        &reloaded.trust_forwarded_prefix,
+    );
+    restart_only(
+        "airport info file",
+        &current.airport_info_file,
+        &reloaded.airport_info_file,
+    );
+    restart_only(
+        "chart aliases file",
+        &current.chart_aliases_file,
+        &reloaded.chart_aliases_file,
+    );
+    restart_only(
+        "integrity check interval",
+        &current.integrity_check_interval,
+        &reloaded.integrity_check_interval,
+    );
+    restart_only(
+        "cycle max age",
+        &current.cycle_max_age,
+        &reloaded.cycle_max_age,
+    );
+    restart_only(
+        "charts max age",
+        &current.charts_max_age,
+        &reloaded.charts_max_age,
+    );
+    restart_only(
+        "groups max age",
+        &current.groups_max_age,
+        &reloaded.groups_max_age,
+    );
+    restart_only(
+        "fetch timeout",
+        &current.fetch_timeout,
+        &reloaded.fetch_timeout,
+    );
+    restart_only(
+        "max in flight",
+        &current.max_in_flight,
+        &reloaded.max_in_flight,
+    );
+    restart_only(
+        "worker threads",
+        &current.worker_threads,
+        &reloaded.worker_threads,
+    );
+    // Logged without the values, which are secret
+    if current.admin_token != reloaded.admin_token {
+        warn!("Admin token changed, restart to apply it");
+    }
+}
+
+fn apply<T: Debug + PartialEq>(name: &str, current: &mut T, reloaded: T) {
+    if *current != reloaded {
+        info!("Reloaded {name}: {current:?} -> {reloaded:?}");
+        *current = reloaded;
+    }
+}
+
+fn restart_only<T: Debug + PartialEq>(name: &str, current: &T, reloaded: &T) {
+    if current != reloaded {
+        warn!("{name} changed to {reloaded:?}, restart to apply it");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(vars: &[(&str, &str)]) -> Config {
+        Config::from_vars(|key| {
+            vars.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| (*value).to_string())
+        })
+    }
+
+    #[test]
+    fn reload_applies_only_hot_reloadable_settings() {
+        let shared = SharedConfig::new(config(&[]));
+        let reloaded = shared.reload(config(&[
+            ("CHARTSAPI_HOT_AIRPORTS", "jfk, lga"),
+            ("CHARTSAPI_EXCLUDE_CODES", "HOT"),
+            ("CHARTSAPI_MIN_CHARTS", "1000"),
+            ("CHARTSAPI_REFRESH_INTERVAL_SECS", "60"),
+            ("CHARTSAPI_CHARTS_MAX_AGE_SECS", "60"),
+            ("CHARTSAPI_CORS_ORIGINS", "https://example.com/, *"),
+        ]));
+        assert_eq!(reloaded.hot_airports, ["JFK", "LGA"]);
+        assert_eq!(reloaded.refresh_interval, Duration::from_mins(1));
+        assert!(reloaded.exclude_codes.is_empty());
+        assert_eq!(reloaded.min_charts, 1);
+        assert_eq!(reloaded.charts_max_age, Duration::from_hours(1));
+        assert_eq!(reloaded.cors_origins, ["https://example.com", "*"]);
+        assert_eq!(shared.get().hot_airports, ["JFK", "LGA"]);
+    }
+}
@@ -0,0 +1,57 @@
+use crate::response_dtos::ChartDto;
+
+pub const HEADER: &str = "state,state_full,city,volume,airport_name,military,faa_ident,icao_ident,\
+chart_seq,chart_code,chart_name,pdf_name,pdf_path,amendment_number,amendment_date,\
+change_notice_section,change_notice_page,bound_volume_section,bound_volume_page,group\r\n";
+
+/// Renders a chart as a CSV row in `HEADER` order, terminated by CRLF as per RFC 4180
+pub fn chart_row(chart: &ChartDto) -> String {
+    let group = format!("{:?}", chart.chart_group);
+    let fields: [&str; 20] = [
+        &chart.state,
+        &chart.state_full,
+        &chart.city,
+        &chart.volume,
+        &chart.airport_name,
+        &chart.military,
+        &chart.faa_ident,
+        chart.icao_ident.as_deref().unwrap_or_default(),
+        &chart.chart_seq,
+        &chart.chart_code,
+        &chart.chart_name,
+        &chart.pdf_name,
+        &chart.pdf_path,
+        &chart.amendment_number,
+        &chart.amendment_date,
+        &chart.pagination.change_notice_section,
+        &chart.pagination.change_notice_page,
+        &chart.pagination.bound_volume_section,
+        &chart.pagination.bound_volume_page,
+        &group,
+    ];
+    let mut row = fields.map(escape).join(",");
+    row.push_str("\r\n");
+    row
+}
+
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_quotes_fields_with_separators() {
+        assert_eq!(escape("JFK"), "JFK");
+        assert_eq!(escape("RWY 4L, CONT.1"), "\"RWY 4L, CONT.1\"");
+        assert_eq!(escape("DALLAS \"LOVE\""), "\"DALLAS \"\"LOVE\"\"\"");
+        assert_eq!(escape("LINE\nBREAK"), "\"LINE\nBREAK\"");
+        assert_eq!(escape("CARRIAGE\rRETURN"), "\"CARRIAGE\rRETURN\"");
+    }
+}
@@ -0,0 +1,39 @@
+use crate::response_dtos::AirportInfoDto;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Source of airport details the metafile lacks, such as elevation and ATIS or weather
+/// frequencies, attached to the airport metadata of chart responses. Kept apart from chart
+/// loading so charts are served the same whether or not a source is configured.
+pub trait AirportEnricher: Send + Sync {
+    fn airport_info(&self, faa_ident: &str) -> Option<AirportInfoDto>;
+}
+
+/// Airport details read once from a JSON file keyed by FAA ident, such as
+/// `{"JFK": {"elevation_ft": 13, "atis_frequency": "128.725"}}`
+pub struct FileAirportEnricher {
+    airports: HashMap<String, AirportInfoDto>,
+}
+
+impl FileAirportEnricher {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let airports: HashMap<String, AirportInfoDto> =
+            serde_json::from_slice(&std::fs::read(path)?)?;
+        Ok(Self {
+            airports: airports
+                .into_iter()
+                .map(|(ident, info)| (ident.to_uppercase(), info))
+                .collect(),
+        })
+    }
+
+    pub fn airport_count(&self) -> usize {
+        self.airports.len()
+    }
+}
+
+impl AirportEnricher for FileAirportEnricher {
+    fn airport_info(&self, faa_ident: &str) -> Option<AirportInfoDto> {
+        self.airports.get(faa_ident).cloned()
+    }
+}
@@ -1,4 +1,11 @@
+use encoding_rs::{Encoding, UTF_8};
+use quick_xml::de::from_str;
+use quick_xml::events::Event;
+use quick_xml::{DeError, Reader};
+use serde::de::Error as _;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use tracing::warn;
 
 #[derive(Serialize, Deserialize)]
 pub struct DigitalTpp {
@@ -12,6 +19,101 @@ pub struct DigitalTpp {
     pub states: Vec<State>,
 }
 
+/// The attributes of a metafile's root element
+pub struct MetafileHeader {
+    pub cycle: String,
+    pub from_effective_date: String,
+    pub to_effective_date: String,
+}
+
+/// Reads a metafile's states one at a time with a streaming reader.
+///
+/// Each state is deserialized on its own, so charts can be processed without holding the whole
+/// deserialized document as well as its XML. Yields the same states as deserializing a
+/// [`DigitalTpp`], stopping at the first error.
+pub struct StateReader<'a> {
+    xml: &'a str,
+    reader: Reader<&'a [u8]>,
+    done: bool,
+}
+
+impl<'a> StateReader<'a> {
+    /// Reads up to the root element, returning the reader positioned at its first state along with
+    /// the root's attributes
+    ///
+    /// # Errors
+    ///
+    /// Fails when the document ends or is malformed before the root element.
+    pub fn new(xml: &'a str) -> Result<(Self, MetafileHeader), DeError> {
+        let mut reader = Reader::from_str(xml);
+        let root = loop {
+            match reader.read_event()? {
+                Event::Start(root) => break root,
+                Event::Eof => return Err(DeError::UnexpectedEof),
+                _ => {}
+            }
+        };
+        let attribute = |name: &'static str| -> Result<String, DeError> {
+            root.try_get_attribute(name)?
+                .ok_or_else(|| DeError::missing_field(name))?
+                .unescape_value()
+                .map(Cow::into_owned)
+                .map_err(DeError::from)
+        };
+        let header = MetafileHeader {
+            cycle: attribute("cycle")?,
+            from_effective_date: attribute("from_edate")?,
+            to_effective_date: attribute("to_edate")?,
+        };
+        Ok((
+            Self {
+                xml,
+                reader,
+                done: false,
+            },
+            header,
+        ))
+    }
+
+    fn next_state(&mut self) -> Result<Option<State>, DeError> {
+        loop {
+            let position = self.offset();
+            let element = match self.reader.read_event()? {
+                Event::Start(element) => {
+                    self.reader.read_to_end(element.name())?;
+                    element
+                }
+                Event::Empty(element) => element,
+                Event::End(_) | Event::Eof => return Ok(None),
+                _ => continue,
+            };
+            if element.name().as_ref() != b"state_code" {
+                continue;
+            }
+            // The element starts at its `<`, after any whitespace read since the last event
+            let start = position + self.xml[position..].find('<').unwrap_or_default();
+            return from_str(&self.xml[start..self.offset()]).map(Some);
+        }
+    }
+
+    fn offset(&self) -> usize {
+        usize::try_from(self.reader.buffer_position()).expect("Positions fit in memory")
+    }
+}
+
+impl Iterator for StateReader<'_> {
+    type Item = Result<State, DeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let state = self.next_state().transpose();
+        self.done = !matches!(state, Some(Ok(_)));
+        state
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct State {
     #[serde(rename = "@ID")]
@@ -22,6 +124,24 @@ pub struct State {
     pub cities: Vec<City>,
 }
 
+/// State and territory codes the FAA publishes charts under: the states, DC, the inhabited
+/// territories, the freely associated states and the minor outlying islands
+const STATE_CODES: [&str; 60] = [
+    "AK", "AL", "AR", "AZ", "CA", "CO", "CT", "DE", "FL", "GA", "HI", "IA", "ID", "IL", "IN", "KS",
+    "KY", "LA", "MA", "MD", "ME", "MI", "MN", "MO", "MS", "MT", "NC", "ND", "NE", "NH", "NJ", "NM",
+    "NV", "NY", "OH", "OK", "OR", "PA", "RI", "SC", "SD", "TN", "TX", "UT", "VA", "VT", "WA", "WI",
+    "WV", "WY", "DC", "AS", "GU", "MP", "PR", "VI", "FM", "MH", "PW", "UM",
+];
+
+impl State {
+    /// Whether the state's `ID` is a known state or territory code, which the metafile doesn't
+    /// guarantee
+    #[must_use]
+    pub fn has_known_code(&self) -> bool {
+        STATE_CODES.contains(&self.id.as_str())
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct City {
     #[serde(rename = "@ID")]
@@ -97,4 +217,252 @@ pub struct Edition {
     pub date: String,
     #[serde(rename = "editionNumber")]
     pub number: String,
+    #[serde(rename = "product", default)]
+    pub products: Vec<Product>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Product {
+    #[serde(rename = "@productName", default)]
+    pub name: String,
+    #[serde(rename = "@url", default)]
+    pub url: String,
+}
+
+/// JSON form of the info endpoint's product set, offered alongside the XML. Editions and products
+/// may come as a single object or a list, and the edition number may be unquoted.
+#[derive(Deserialize)]
+pub struct JsonProductSet {
+    pub edition: OneOrMany<JsonEdition>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonEdition {
+    pub geoname: String,
+    pub edition_name: String,
+    pub format: String,
+    pub edition_date: String,
+    pub edition_number: StringOrNumber,
+    #[serde(default)]
+    pub product: Option<OneOrMany<JsonProduct>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonProduct {
+    #[serde(default)]
+    pub product_name: String,
+    #[serde(default)]
+    pub url: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    pub fn into_vec(self) -> Vec<T> {
+        match self {
+            Self::One(value) => vec![value],
+            Self::Many(values) => values,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum StringOrNumber {
+    String(String),
+    Number(u64),
+}
+
+impl JsonProductSet {
+    /// The first edition, in the same form as the XML's
+    pub fn into_edition(self) -> Option<Edition> {
+        let edition = self.edition.into_vec().into_iter().next()?;
+        Some(Edition {
+            geoname: edition.geoname,
+            name: edition.edition_name,
+            format: edition.format,
+            date: edition.edition_date,
+            number: match edition.edition_number {
+                StringOrNumber::String(number) => number,
+                StringOrNumber::Number(number) => number.to_string(),
+            },
+            products: edition
+                .product
+                .map(OneOrMany::into_vec)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|product| Product {
+                    name: product.product_name,
+                    url: product.url,
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Decodes an FAA document.
+///
+/// It has at times shipped with a byte order mark or in an encoding other than UTF-8. A BOM takes
+/// precedence, then the encoding declared by the XML declaration, falling back to UTF-8.
+/// Undecodable bytes are replaced rather than failing the whole document.
+pub fn decode_document(bytes: &[u8]) -> String {
+    let encoding = Encoding::for_bom(bytes)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| declared_encoding(bytes))
+        .unwrap_or(UTF_8);
+    let (text, had_errors) = encoding.decode_with_bom_removal(bytes);
+    if had_errors {
+        warn!(
+            "Replaced malformed {} sequences in FAA document",
+            encoding.name()
+        );
+    }
+    text.into_owned()
+}
+
+/// The `encoding` of an XML declaration such as `<?xml version="1.0" encoding="ISO-8859-1"?>`
+fn declared_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    let declaration = bytes.strip_prefix(b"<?xml")?;
+    let declaration = &declaration[..declaration.windows(2).position(|w| w == b"?>")?];
+    let declaration = std::str::from_utf8(declaration).ok()?;
+    let (_, value) = declaration.split_once("encoding")?;
+    let value = value.trim_start().strip_prefix('=')?.trim_start();
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let label = value[1..].split(quote).next()?;
+    Encoding::for_label(label.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METAFILE: &str = include_str!("../tests/fixtures/d-tpp_Metafile.xml");
+
+    fn assert_reads_like_from_str(xml: &str) {
+        let dtpp = from_str::<DigitalTpp>(xml).unwrap();
+        let (states, header) = StateReader::new(xml).unwrap();
+        let states = states.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(header.cycle, dtpp.cycle);
+        assert_eq!(header.from_effective_date, dtpp.from_effective_date);
+        assert_eq!(header.to_effective_date, dtpp.to_effective_date);
+        assert_eq!(
+            serde_json::to_value(&states).unwrap(),
+            serde_json::to_value(&dtpp.states).unwrap()
+        );
+    }
+
+    #[test]
+    fn state_reader_matches_from_str() {
+        assert_reads_like_from_str(METAFILE);
+    }
+
+    #[test]
+    fn state_reader_matches_from_str_with_entities_and_comments() {
+        assert_reads_like_from_str(
+            r#"<?xml version="1.0"?>
+<!DOCTYPE digital_tpp>
+<!-- leading comment -->
+<digital_tpp cycle="2410" from_edate="0901Z  10/03/24" to_edate="0901Z  10/31/24">
+<state_code ID="TX" state_fullname="Texas &amp; Co">
+<!-- a comment -->
+<city_name ID="DALLAS" volume="SC-2">
+<airport_name ID="DALLAS &quot;LOVE&quot;" military="N" apt_ident="DAL" icao_ident="KDAL" alnum="1">
+<record><chartseq>10100</chartseq><chart_code>IAP</chart_code><chart_name>RNAV &lt;GPS&gt; RWY 13L</chart_name><useraction/><pdf_name>DAL1.PDF</pdf_name><cn_flg>N</cn_flg><cnsection/><cnpage/><bvsection>C</bvsection><bvpage/><procuid/><two_colored>N</two_colored><civil/><faanfd18/><copter/><amdtnum/><amdtdate/></record>
+</airport_name>
+</city_name>
+</state_code>
+</digital_tpp>"#,
+        );
+    }
+
+    #[test]
+    fn state_reader_stops_at_the_first_error() {
+        let (states, _) = StateReader::new(&METAFILE[..METAFILE.len() / 2]).unwrap();
+        let states = states.collect::<Vec<_>>();
+        assert!(states.last().unwrap().is_err());
+        assert_eq!(states.iter().filter(|state| state.is_err()).count(), 1);
+    }
+
+    #[test]
+    fn info_edition_reads_number_and_products() {
+        let xml = from_str::<ProductSet>(include_str!("../tests/fixtures/dtpp_info.xml"))
+            .unwrap()
+            .edition;
+        let json = serde_json::from_str::<JsonProductSet>(include_str!(
+            "../tests/fixtures/dtpp_info.json"
+        ))
+        .unwrap()
+        .into_edition()
+        .unwrap();
+        for edition in [xml, json] {
+            assert_eq!(edition.name, "CURRENT");
+            assert_eq!(edition.date, "10/03/2024");
+            assert_eq!(edition.number, "10");
+            assert_eq!(edition.products.len(), 1);
+            assert_eq!(
+                edition.products[0].name,
+                "DIGITAL-TERMINAL PROCEDURES PUBLICATION"
+            );
+            assert_eq!(
+                edition.products[0].url,
+                "https://aeronav.faa.gov/upload_313-d/terminal/DDTPPA_241003.zip"
+            );
+        }
+    }
+
+    #[test]
+    fn decode_document_strips_a_bom() {
+        let bom = [b"\xEF\xBB\xBF", METAFILE.as_bytes()].concat();
+        assert_eq!(decode_document(&bom), METAFILE);
+    }
+
+    #[test]
+    fn decode_document_uses_the_declared_encoding() {
+        let latin1 = METAFILE
+            .replace(r#"encoding="UTF-8""#, r#"encoding="ISO-8859-1""#)
+            .replace("LUIS MUNOZ MARIN", "LUIS MUÑOZ MARÍN");
+        let (bytes, _, _) = encoding_rs::WINDOWS_1252.encode(&latin1);
+        assert!(std::str::from_utf8(&bytes).is_err());
+        let xml = decode_document(&bytes);
+        assert_eq!(xml, latin1);
+        let (states, _) = StateReader::new(&xml).unwrap();
+        let airport = states
+            .map(Result::unwrap)
+            .flat_map(|state| state.cities)
+            .flat_map(|city| city.airports)
+            .find(|airport| airport.apt_ident == "SJU")
+            .unwrap();
+        assert_eq!(airport.id, "LUIS MUÑOZ MARÍN INTL");
+    }
+
+    #[test]
+    fn decode_document_replaces_malformed_utf8() {
+        assert_eq!(decode_document(b"<a>\xFF</a>"), "<a>\u{FFFD}</a>");
+    }
+
+    #[test]
+    fn state_codes_are_checked_against_known_codes() {
+        let state = |id: &str| State {
+            id: id.to_string(),
+            full_name: String::new(),
+            cities: Vec::new(),
+        };
+        for known in ["NY", "TX", "DC", "PR", "GU", "UM"] {
+            assert!(state(known).has_known_code(), "{known}");
+        }
+        for unknown in ["XX", "ny", "", "NYC", "EG"] {
+            assert!(!state(unknown).has_known_code(), "{unknown}");
+        }
+        let (states, _) = StateReader::new(METAFILE).unwrap();
+        assert!(states
+            .map(Result::unwrap)
+            .all(|state| state.has_known_code()));
+    }
 }
@@ -0,0 +1,7 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+//! Metafile parsing and chart lookup, shared by the server with its benchmarks and fuzz targets
+
+pub mod charts;
+pub mod config;
+pub mod faa_metafile;
+pub mod response_dtos;
@@ -1,98 +1,679 @@
 #![warn(clippy::all, clippy::pedantic, clippy::nursery)]
 
-use crate::faa_metafile::{DigitalTpp, ProductSet};
-use crate::response_dtos::ResponseDto::{Charts, GroupedCharts};
-use crate::response_dtos::{ChartDto, ChartGroup, GroupedChartsDto, ResponseDto};
-use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
+use crate::cache::{ChartCache, InMemoryChartCache};
+use crate::chart_aliases::ChartAliases;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::enrichment::{AirportEnricher, FileAirportEnricher};
+use crate::faa_metafile::{JsonProductSet, ProductSet, StateReader};
+use crate::response_dtos::ResponseDto::{Both, GroupedCharts, LabeledGroups, SnakeCaseGroups};
+use crate::response_dtos::{
+    parse_runway, AirportChartsDto, AirportDebugDto, AirportMetaDto, AirportResponseDto,
+    BothShapesDto, ChartDto, ChartGroup, ChartLinksDto, ChartsResponseDto, CycleDto, DebugDto,
+    EditionDto, ExpandedAirportChartsDto, ExpandedAirportDto, GroupCodeDto, LogFilterDto,
+    LookupPath, PdfLinkDto, ProductDto, ResolvedIdentDto, ResolvedIdentsDto, ResponseDto, StatsDto,
+};
+use crate::single_flight::SingleFlight;
+use axum::body::{Body, Bytes};
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{FromRef, Path, Query, Request, State};
+use axum::handler::HandlerWithoutStateExt;
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use axum::middleware::{self, Next};
 use axum::response::{IntoResponse, Redirect, Response};
-use axum::routing::get;
-use axum::{Json, Router};
-use chrono::{NaiveDate, NaiveDateTime, Utc};
+use axum::routing::{get, post};
+use axum::{Json, Router, ServiceExt};
+use chartsapi_rs::charts::{
+    apply_group_param, build_hashmaps, cycle_url, filter_group_by_types, group_types, splits_apd,
+    ChartsHashMaps, GroupOutput, GROUP_1_TYPES, GROUP_CODES,
+};
+use chartsapi_rs::config::{Config, SharedConfig};
+use chartsapi_rs::{config, faa_metafile, response_dtos};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use futures_util::stream;
 use indexmap::IndexMap;
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 use quick_xml::de::from_str;
+use reqwest::redirect::Policy;
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::any::Any;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::hash::{BuildHasher, DefaultHasher, Hash, Hasher, RandomState};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{Arc, LazyLock, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio_util::io::ReaderStream;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::normalize_path::NormalizePath;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::services::ServeDir;
+use tower_http::set_header::SetResponseHeaderLayer;
 use tower_http::trace::TraceLayer;
-use tracing::{debug, info, warn};
+use tracing::level_filters::LevelFilter;
+use tracing::{debug, debug_span, error, info, warn, Span};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
 
-mod faa_metafile;
-mod response_dtos;
+mod cache;
+mod chart_aliases;
+mod circuit_breaker;
+mod csv_export;
+mod enrichment;
+mod single_flight;
+mod sqlite_export;
+mod validate;
 
-struct ChartsHashMaps {
-    faa: IndexMap<String, Vec<ChartDto>>,
-    icao: IndexMap<String, String>,
+/// Everything loaded for a cycle, swapped as a whole so readers always see a consistent
+/// snapshot. Since writers only swap it or assign single fields, a writer that panicked can't
+/// leave it half-updated, so its lock's poisoning is ignored rather than failing every request.
+struct AppState {
+    cycle: String,
+    from_effective_date: DateTime<Utc>,
+    to_effective_date: DateTime<Utc>,
+    loaded_at: DateTime<Utc>,
+    metafile_hash: u64,
+    validators: MetafileValidators,
+    hashmaps: Arc<ChartsHashMaps>,
+    edition: Option<EditionDto>,
+    /// Loaded from the metafile embedded at build time rather than the FAA
+    embedded: bool,
+    /// The metafile exactly as downloaded, only kept when the admin token is configured to serve it
+    metafile: Option<Bytes>,
+    /// Serialized responses of hot airports for this cycle, filled in shortly after loading
+    prewarmed: Arc<HashMap<String, Bytes>>,
 }
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
+#[derive(Clone)]
+struct ServerState {
+    app_state: Arc<RwLock<AppState>>,
+    client: Client,
+    config: SharedConfig,
+    cache: Arc<dyn ChartCache>,
+    enricher: Option<Arc<dyn AirportEnricher>>,
+    aliases: Arc<ChartAliases>,
+    log_filter: LogFilterHandle,
+}
+
+impl FromRef<ServerState> for Arc<RwLock<AppState>> {
+    fn from_ref(state: &ServerState) -> Self {
+        Self::clone(&state.app_state)
+    }
+}
+
+/// Number of past cycles loaded on demand that are kept by the default cache
+const CYCLE_CACHE_CAPACITY: usize = 4;
+
+/// Length of an FAA chart cycle in days
+const CYCLE_DAYS: i64 = 28;
+
+/// Maximum number of redirects followed for any FAA request before giving up
+const MAX_REDIRECTS: usize = 5;
+
+/// Outbound calls to the FAA stop for a while after this many consecutive failures
+const FAA_FAILURE_THRESHOLD: u32 = 5;
+
+/// How long calls to the FAA are paused once the breaker opens, before probing again
+const FAA_COOLDOWN: Duration = Duration::from_mins(1);
+
+fn main() -> ExitCode {
+    // The filter is reloadable, so it starts permissive until the config it comes from is loaded
+    let (log_filter, log_filter_handle) = reload::Layer::new(level_filter(LevelFilter::DEBUG));
+    tracing_subscriber::registry()
+        .with(log_filter)
+        .with(tracing_subscriber::fmt::layer())
         .init();
 
-    // Initialize current_cycle and in-memory hashmaps for FAA/ICAO id lookup
-    let current_cycle = RwLock::new(fetch_current_cycle().await.unwrap_or_else(|e| {
-        warn!(
-            "Error initializing current cycle, falling back to default: {}",
-            e
-        );
-        "2411".to_string()
-    }));
-    let cycle_clone = current_cycle.read().unwrap().clone();
-    let hashmaps = Arc::new(RwLock::new(
-        load_charts(&cycle_clone)
-            .await
-            .expect("Could not fetch and initialize charts"),
+    let config = SharedConfig::new(Config::load());
+    if let Err(e) = log_filter_handle.reload(level_filter(config.get().log_level)) {
+        warn!("Could not set log level: {e}");
+    }
+
+    // Defaults to one worker per core like `#[tokio::main]`, unless limited for small hosts
+    let worker_threads = config.get().worker_threads.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+    });
+    info!("Starting runtime with {worker_threads} worker threads");
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads)
+        .enable_all()
+        .build()
+        .expect("Could not build runtime")
+        .block_on(run(config, log_filter_handle))
+}
+
+async fn run(shared_config: SharedConfig, log_filter: LogFilterHandle) -> ExitCode {
+    let config = shared_config.get();
+    // Validate a metafile without starting the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--validate") {
+        let Some(source) = args.get(1) else {
+            eprintln!("Usage: chartsapi-rs --validate <path-or-url>");
+            return ExitCode::FAILURE;
+        };
+        return validate::run(&config, source).await;
+    }
+
+    let metrics_handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full("chartsapi_load_duration_seconds".to_string()),
+            &[0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0],
+        )
+        .and_then(PrometheusBuilder::install_recorder)
+        .expect("Could not install metrics recorder");
+    let client = build_client(config.fetch_timeout).expect("Could not build HTTP client");
+
+    // Initialize current cycle and in-memory hashmaps for FAA/ICAO id lookup
+    let (current_cycle, edition) = match fetch_current_cycle(&client, &config.faa_info_url).await {
+        Ok((cycle, edition)) => (cycle, Some(edition)),
+        Err(e) => {
+            warn!(
+                "Error initializing current cycle, falling back to default: {}",
+                e
+            );
+            ("2411".to_string(), None)
+        }
+    };
+    let mut initial_state = match load_charts(&client, &config, &current_cycle).await {
+        Ok(state) => state,
+        #[cfg(feature = "embedded-metafile")]
+        Err(e) => {
+            warn!("Could not fetch charts, serving the embedded metafile as stale: {e}");
+            load_embedded_charts(&config).expect("Could not initialize embedded charts")
+        }
+        #[cfg(not(feature = "embedded-metafile"))]
+        Err(e) => panic!("Could not fetch and initialize charts: {e:?}"),
+    };
+    initial_state.edition = edition;
+    let app_state = Arc::new(RwLock::new(initial_state));
+    tokio::spawn(prewarm_responses(
+        Arc::clone(&app_state),
+        config.hot_airports.clone(),
     ));
-    let axum_state = Arc::clone(&hashmaps);
+    let axum_state = ServerState {
+        app_state: Arc::clone(&app_state),
+        client: client.clone(),
+        config: shared_config.clone(),
+        cache: Arc::new(InMemoryChartCache::new(CYCLE_CACHE_CAPACITY)),
+        enricher: load_enricher(&config),
+        aliases: Arc::new(load_chart_aliases(&config)),
+        log_filter: log_filter.clone(),
+    };
+
+    // Spawn opt-in integrity check comparing the live charts against a fresh load
+    if let Some(interval) = config.integrity_check_interval {
+        let client = client.clone();
+        let shared_config = shared_config.clone();
+        let app_state = Arc::clone(&app_state);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                check_integrity(&client, &shared_config.get(), &app_state).await;
+            }
+        });
+    }
 
     // Spawn cycle and chart update loop
-    tokio::spawn(async move {
-        loop {
-            tokio::time::sleep(Duration::from_secs(3600)).await;
-            match fetch_current_cycle().await {
-                Ok(fetched_cycle) => {
-                    if fetched_cycle.eq_ignore_ascii_case(&current_cycle.read().unwrap()) {
-                        debug!("No new cycle found");
-                        continue;
-                    }
+    tokio::spawn(refresh_charts(
+        client,
+        shared_config.clone(),
+        Arc::clone(&app_state),
+    ));
 
-                    info!("Found new cycle: {fetched_cycle}");
-                    match load_charts(&fetched_cycle).await {
-                        Ok(new_charts) => {
-                            *hashmaps.write().unwrap() = new_charts;
-                            *current_cycle.write().unwrap() = fetched_cycle;
-                        }
-                        Err(e) => warn!("Error while fetching charts: {}", e),
-                    }
-                }
-                Err(e) => warn!("Error while fetching current cycle: {}", e),
-            }
-        }
-    });
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(shared_config, log_filter, app_state));
+    #[cfg(not(unix))]
+    drop((shared_config, log_filter, app_state));
+
+    // Resolve the static directory up front so it doesn't depend on the working directory
+    let assets_dir =
+        std::path::absolute(&config.assets_dir).unwrap_or_else(|_| config.assets_dir.clone());
+    if assets_dir.is_dir() {
+        info!("Serving static charts from {}", assets_dir.display());
+    } else {
+        warn!(
+            "Static charts directory {} does not exist",
+            assets_dir.display()
+        );
+    }
 
     // Create and run axum app
-    let app = Router::new()
-        .route("/v1/charts", get(charts_handler))
-        .nest_service("/v1/charts/static", ServeDir::new("assets"))
+    // Trailing slashes are trimmed before routing, so `/v1/charts/` is served as `/v1/charts`
+    let app =
+        NormalizePath::trim_trailing_slash(build_router(axum_state, assets_dir, metrics_handle));
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
+    axum::serve(listener, ServiceExt::<Request>::into_make_service(app))
+        .await
+        .unwrap();
+    ExitCode::SUCCESS
+}
+
+fn build_router(
+    state: ServerState,
+    assets_dir: PathBuf,
+    metrics_handle: PrometheusHandle,
+) -> Router {
+    let config = state.config.get();
+    let charts_cache = cache_control(config.charts_max_age);
+
+    // Chart responses vary with content negotiation, so shared caches must key on these headers,
+    // and with the forwarded prefix in their links when it is trusted
+    let vary = if config.trust_forwarded_prefix {
+        "Accept, Accept-Encoding, X-Forwarded-Prefix"
+    } else {
+        "Accept, Accept-Encoding"
+    };
+    let chart_routes = Router::new()
+        .route(
+            "/v1/charts",
+            get(charts_handler)
+                .head(charts_head_handler)
+                .layer(charts_cache.clone()),
+        )
+        .route(
+            "/v1/charts/:apt_id",
+            get(airport_charts_handler).layer(charts_cache.clone()),
+        )
+        .route(
+            "/v1/charts/:apt_id/exact",
+            get(exact_chart_handler).layer(charts_cache),
+        )
+        .route("/v1/charts/:apt_id/diagram.pdf", get(diagram_pdf_handler))
+        .route("/v1/charts/:apt_id/search", get(chart_search_query_handler))
+        .route("/v1/charts/:apt_id/groups", get(airport_groups_handler))
+        .route("/v1/charts/:apt_id/pdf-urls", get(pdf_urls_handler))
+        .route(
+            "/v1/charts/:apt_id/latest-amendment",
+            get(latest_amendment_handler),
+        )
         .route(
             "/v1/charts/:apt_id/:chart_search_term",
-            get(chart_search_handler),
+            get(chart_search_handler).head(chart_search_head_handler),
+        )
+        .route(
+            "/v1/charts/:apt_id/:chart_search_term/pdf",
+            get(chart_pdf_handler),
+        )
+        .layer(SetResponseHeaderLayer::overriding(
+            header::VARY,
+            HeaderValue::from_static(vary),
+        ))
+        .route("/v1/charts.csv", get(charts_csv_handler))
+        .layer(middleware::from_fn_with_state(state.clone(), expiry_guard));
+
+    let cors = cors_layer(state.config.clone());
+    let router = Router::new()
+        .merge(chart_routes)
+        .nest_service(
+            "/v1/charts/static",
+            ServeDir::new(assets_dir).not_found_service(static_not_found.into_service()),
+        )
+        .route(
+            "/v1/cycle",
+            get(cycle_handler).layer(cache_control(config.cycle_max_age)),
+        )
+        .route(
+            "/v1/groups",
+            get(groups_handler).layer(cache_control(config.groups_max_age)),
         )
+        .route(
+            "/v1/stats",
+            get(stats_handler).layer(cache_control(config.cycle_max_age)),
+        )
+        .route("/v1/chart-codes", get(chart_codes_handler))
+        .route("/v1/idents/resolve", post(resolve_idents_handler))
+        .route("/v1/metafile.xml", get(metafile_handler))
+        .route("/v1/export.sqlite", get(sqlite_export_handler))
+        .route("/v1/admin/log-filter", post(log_filter_handler))
+        // Added before health and metrics, so those keep answering while requests are shed
+        .layer(middleware::from_fn_with_state(
+            Arc::new(Semaphore::new(config.max_in_flight)),
+            limit_in_flight,
+        ))
         .route("/health", get(|| async {}))
-        .with_state(axum_state)
-        .layer(TraceLayer::new_for_http());
+        .route(
+            "/metrics",
+            get(move || std::future::ready(metrics_handle.render())),
+        )
+        .with_state(state);
+    let router = with_request_ids(router).layer(cors);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // For proxies that forward the prefix instead of stripping it
+    if config.nest_base_path && !config.base_path.is_empty() {
+        Router::new().nest(&config.base_path, router)
+    } else {
+        router
+    }
+}
+
+/// Tags each request and its response with an `x-request-id`, generated unless the client sent
+/// one, and answers panics within it
+fn with_request_ids(router: Router) -> Router {
+    router
+        // Inside the trace layer, so panics are logged within the request's span and its ID
+        .layer(CatchPanicLayer::custom(panic_response))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+/// The trace layer's default span, plus the request ID so every line logged for it can be found
+fn request_span(request: &Request) -> Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    debug_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        request_id,
+    )
+}
+
+/// Lets browsers on the configured origins read responses, checking the current config on each
+/// request so reloaded origins apply straight away. No origin is allowed while none are set.
+fn cors_layer(config: SharedConfig) -> CorsLayer {
+    CorsLayer::new()
+        .allow_methods([Method::GET, Method::HEAD, Method::POST])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+        .allow_origin(AllowOrigin::predicate(move |origin, _| {
+            config
+                .get()
+                .cors_origins
+                .iter()
+                .any(|allowed| allowed == "*" || allowed.as_bytes() == origin.as_bytes())
+        }))
+}
+
+/// Sheds requests beyond the configured maximum in flight with a 503 instead of queueing them, so
+/// a small host degrades rather than running out of memory or file descriptors
+async fn limit_in_flight(
+    State(limit): State<Arc<Semaphore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Ok(permit) = limit.try_acquire() else {
+        counter!("chartsapi_shed_requests_total").increment(1);
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorMessage {
+                status: "error",
+                status_code: "503",
+                message: "Too many requests in flight.",
+            }),
+        )
+            .into_response();
+    };
+    let _in_flight = InFlight::new(permit);
+    next.run(request).await
+}
+
+/// Counts a request in the in-flight gauge until dropped, even if the request is cancelled
+struct InFlight<'a> {
+    _permit: SemaphorePermit<'a>,
+}
+
+impl<'a> InFlight<'a> {
+    fn new(permit: SemaphorePermit<'a>) -> Self {
+        gauge!("chartsapi_in_flight_requests").increment(1.0);
+        Self { _permit: permit }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        gauge!("chartsapi_in_flight_requests").decrement(1.0);
+    }
 }
 
+/// Answers a panicking handler, e.g. on a poisoned lock, with the usual error JSON instead of
+/// dropping the connection. It's logged within the request's span, which carries its
+/// `x-request-id` so the client's report can be matched to the panic.
+#[allow(clippy::needless_pass_by_value)] // Signature required by `CatchPanicLayer`
+fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    error!("Handler panicked: {message}");
+    counter!("chartsapi_panics_total").increment(1);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorMessage {
+            status: "error",
+            status_code: "500",
+            message: "Internal server error.",
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct CycleParam {
+    cycle: Option<String>,
+}
+
+/// Serves the current cycle as stale for `expiry_grace` after it expires, as the FAA sometimes
+/// publishes the next cycle late, then refuses it. Explicitly requested past cycles are exempt,
+/// and embedded charts are always served as stale since they are the last resort.
+async fn expiry_guard(State(state): State<ServerState>, request: Request, next: Next) -> Response {
+    let (expired_for, embedded) = {
+        let reader = state
+            .app_state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        (Utc::now() - reader.to_effective_date, reader.embedded)
+    };
+    let past_cycle = Query::<CycleParam>::try_from_uri(request.uri())
+        .is_ok_and(|Query(param)| param.cycle.is_some());
+    if past_cycle || (expired_for <= TimeDelta::zero() && !embedded) {
+        return next.run(request).await;
+    }
+    if !embedded && expired_for.to_std().unwrap_or_default() > state.config.get().expiry_grace {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorMessage {
+                status: "error",
+                status_code: "503",
+                message: "The loaded charts cycle has expired.",
+            }),
+        )
+            .into_response();
+    }
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(
+        header::WARNING,
+        HeaderValue::from_static("110 - \"Response is Stale\""),
+    );
+    response
+}
+
+/// Lets shared caches keep successful responses for `max_age`. Errors are never stored, so a
+/// transient failure isn't served in place of the charts for as long.
+fn cache_control(
+    max_age: Duration,
+) -> SetResponseHeaderLayer<impl Fn(&Response) -> Option<HeaderValue> + Clone> {
+    let public = HeaderValue::from_str(&format!("public, max-age={}", max_age.as_secs()))
+        .expect("Cache-Control value is always valid");
+    SetResponseHeaderLayer::if_not_present(header::CACHE_CONTROL, move |response: &Response| {
+        let status = response.status();
+        Some(
+            if status.is_success() || status == StatusCode::NOT_MODIFIED {
+                public.clone()
+            } else {
+                HeaderValue::from_static("no-store")
+            },
+        )
+    })
+}
+
+// Each flag is an independent query parameter, so they have no state machine to fold into
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Deserialize)]
 struct ChartsOptions {
     apt: Option<String>,
     group: Option<i32>,
+    #[serde(default)]
+    group_format: GroupFormat,
+    #[serde(default)]
+    naming: GroupNaming,
+    amended_within: Option<u32>,
+    min_amendment_num: Option<u32>,
+    #[serde(default)]
+    group_empty: GroupEmpty,
+    #[serde(default)]
+    order: ChartOrder,
+    cycle: Option<String>,
+    #[serde(default)]
+    empty: EmptyResults,
+    #[serde(default)]
+    shape: ResponseShape,
+    per_airport_limit: Option<usize>,
+    #[serde(default)]
+    key: ResultKey,
+    #[serde(default)]
+    key_case: KeyCase,
+    runway: Option<String>,
+    #[serde(default = "include_icao_default")]
+    include_icao: bool,
+    #[serde(default)]
+    airport_meta: bool,
+    expand: Option<Expand>,
+    #[serde(default)]
+    hateoas: bool,
+    #[serde(default)]
+    flatten: bool,
+    #[serde(default)]
+    debug: bool,
+}
+
+const fn include_icao_default() -> bool {
+    true
+}
+
+/// Shape of grouped output: `keys` is upstream-compatible, `labeled` emits `code`/`label`/`charts`
+#[derive(Clone, Copy, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum GroupFormat {
+    #[default]
+    Keys,
+    Labeled,
+}
+
+/// Keys of grouped output: the upstream `General`/`DP`/`STAR`/`CAPP` codes (default), or snake case
+/// names matching the chart fields
+#[derive(Clone, Copy, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum GroupNaming {
+    #[default]
+    Faa,
+    Snake,
+}
+
+/// Whether grouped output omits groups without charts (default) or includes them as empty arrays
+#[derive(Clone, Copy, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum GroupEmpty {
+    #[default]
+    Omit,
+    Include,
+}
+
+/// Chart ordering within each airport: metafile order (default), by `chart_seq`, or in the order
+/// of the printed d-TPP volumes
+#[derive(Clone, Copy, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ChartOrder {
+    #[default]
+    Metafile,
+    Seq,
+    Book,
+}
+
+/// Related objects to embed in each airport's results instead of repeating them on every chart
+#[derive(Clone, Copy, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Expand {
+    Airport,
+}
+
+/// How airports left without charts by filtering are reported: as an empty entry (default), or
+/// omitted from the results and listed under `empty`
+#[derive(Clone, Copy, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum EmptyResults {
+    #[default]
+    Include,
+    List,
+}
+
+/// Each airport's charts as a single shape chosen by `group` (default), or as an object holding
+/// both the flat list and the grouped charts, for clients showing both views
+#[derive(Clone, Copy, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ResponseShape {
+    #[default]
+    Single,
+    Both,
+}
+
+/// Which ident keys each airport's results: its canonical FAA (default) or ICAO ident, or the one
+/// requested
+#[derive(Clone, Copy, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ResultKey {
+    #[default]
+    Faa,
+    Icao,
+    Requested,
+}
+
+/// Casing of the airport idents keying the results, which leaves the charts themselves untouched
+#[derive(Clone, Copy, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum KeyCase {
+    #[default]
+    Upper,
+    Lower,
+}
+
+/// Output formatting shared by the list endpoints: compact by default, indented with `pretty=true`
+#[derive(Clone, Copy, Deserialize)]
+struct JsonFormat {
+    #[serde(default)]
+    pretty: bool,
+}
+
+fn json_response<T: Serialize>(value: &T, format: JsonFormat) -> Response {
+    let body = if format.pretty {
+        serde_json::to_vec_pretty(value)
+    } else {
+        serde_json::to_vec(value)
+    };
+    match body {
+        Ok(body) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            body,
+        )
+            .into_response(),
+        Err(e) => {
+            warn!("Error while serializing response: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -102,251 +683,3985 @@ struct ErrorMessage {
     pub message: &'static str,
 }
 
+/// An error caused by the FAA, with the status it answered with if it got that far
+#[derive(Serialize)]
+struct UpstreamErrorDto {
+    #[serde(flatten)]
+    error: ErrorMessage,
+    upstream_status: Option<u16>,
+}
+
+/// A chart search miss with the names of similar charts at the airport
+#[derive(Serialize)]
+struct ChartSuggestionsDto {
+    #[serde(flatten)]
+    error: ErrorMessage,
+    suggestions: Vec<String>,
+}
+
 async fn charts_handler(
-    State(hashmaps): State<Arc<RwLock<ChartsHashMaps>>>,
-    options: Query<ChartsOptions>,
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    uri: Uri,
+    options: Result<Query<ChartsOptions>, QueryRejection>,
+    format: Result<Query<JsonFormat>, QueryRejection>,
 ) -> Response {
-    let Query(chart_options) = options;
+    let Ok(Query(mut chart_options)) = options else {
+        return invalid_charts_query(&uri);
+    };
+    let Ok(Query(format)) = format else {
+        return invalid_query();
+    };
+    if let Some(response) = prewarmed_response(&state.app_state, &uri, &headers) {
+        return response;
+    }
 
-    // Check that we have an airport to lookup
-    if chart_options.apt.is_none()
-        || chart_options
-            .apt
-            .as_ref()
-            .is_some_and(|s| s.trim().is_empty())
-    {
+    // Clients that can't set query params may ask for grouped output through the Accept profile
+    if chart_options.group.is_none() && accept_profile(&headers).as_deref() == Some("grouped") {
+        chart_options.group = Some(1);
+    }
+
+    if let Some(response) = reject_charts_options(&chart_options) {
+        return response;
+    }
+    // Flattened charts keep their airport fields, so they're never hoisted out
+    if chart_options.flatten {
+        chart_options.airport_meta = false;
+        chart_options.expand = None;
+    }
+
+    // Serve a past cycle when requested, which must be a four digit cycle like 2411
+    let (hashmaps, cycle, prewarmed) = match chart_options.cycle.as_deref() {
+        None => {
+            let reader = state
+                .app_state
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+            (
+                Arc::clone(&reader.hashmaps),
+                reader.cycle.clone(),
+                Arc::clone(&reader.prewarmed),
+            )
+        }
+        Some(cycle) if cycle.len() != 4 || !cycle.bytes().all(|b| b.is_ascii_digit()) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorMessage {
+                    status: "error",
+                    status_code: "400",
+                    message: "That is not a valid cycle.",
+                }),
+            )
+                .into_response();
+        }
+        Some(cycle) => match load_cycle(&state, cycle).await {
+            Ok(hashmaps) => (hashmaps, cycle.to_string(), Arc::default()),
+            Err(e) => return cycle_load_error_response(cycle, &e),
+        },
+    };
+
+    let links_base = chart_options
+        .hateoas
+        .then(|| links_base(&state.config.get(), &headers));
+    let mut results =
+        build_charts_response(&hashmaps, &chart_options, &cycle, links_base.as_deref());
+    if chart_options.flatten {
+        return with_cycle_header(json_response(&flatten_charts(results), format), &cycle);
+    }
+    if let Some(enricher) = &state.enricher {
+        enrich_airports(&mut results, enricher.as_ref());
+    }
+    if let Some(debug) = results.debug.as_mut() {
+        for (ident, airport) in &mut debug.airports {
+            airport.prewarmed = prewarmed.contains_key(ident);
+        }
+    }
+    with_cycle_header(json_response(&results, format), &cycle)
+}
+
+/// Answers a failed on-demand load of a past cycle. Only the FAA not having the cycle means it
+/// doesn't exist and only failed FAA requests are theirs, while a metafile that isn't effective yet
+/// or that we couldn't load is reported as such.
+fn cycle_load_error_response(cycle: &str, e: &anyhow::Error) -> Response {
+    if upstream_status(e) == Some(404) {
+        info!("Cycle {cycle} was not found at the FAA");
         return (
             StatusCode::NOT_FOUND,
             Json(ErrorMessage {
                 status: "error",
                 status_code: "404",
-                message: "Please specify an airport.",
+                message: "Could not load charts for that cycle.",
             }),
         )
             .into_response();
     }
-
-    // Check if supplied chart group is valid, if given as param
-    if chart_options.group.is_some_and(|i| !(1..=7).contains(&i)) {
+    if e.chain()
+        .any(|e| e.is::<UpstreamError>() || e.is::<CircuitOpen>())
+    {
+        warn!("Error while fetching cycle {cycle}: {e}");
+        return faa_error_response(e, "Could not load charts for that cycle from the FAA.");
+    }
+    if e.chain()
+        .any(<dyn std::error::Error>::is::<NotYetEffective>)
+    {
+        info!("Cycle {cycle} was requested before it is effective: {e}");
         return (
-            StatusCode::FORBIDDEN,
+            StatusCode::CONFLICT,
             Json(ErrorMessage {
                 status: "error",
-                status_code: "403",
-                message: "That is not a valid grouping code.",
+                status_code: "409",
+                message: "That cycle is not effective yet.",
             }),
         )
             .into_response();
     }
-
-    let mut results: IndexMap<String, ResponseDto> = IndexMap::new();
-    for airport in chart_options.apt.unwrap().split(',') {
-        let airport_uppercase = airport.to_uppercase();
-        if let Some(charts) = lookup_charts(&airport_uppercase, &hashmaps) {
-            results.insert(
-                airport_uppercase,
-                apply_group_param(&charts, chart_options.group),
-            );
-        }
-    }
-    (StatusCode::OK, Json(results)).into_response()
-}
-
-fn lookup_charts(apt_id: &str, hashmaps: &Arc<RwLock<ChartsHashMaps>>) -> Option<Vec<ChartDto>> {
-    let reader = hashmaps.read().unwrap();
-    reader.faa.get(apt_id).map_or_else(
-        || {
-            reader
-                .icao
-                .get(&apt_id.to_uppercase())
-                .and_then(|faa_id| reader.faa.get(faa_id).cloned())
-        },
-        |charts| Some(charts.clone()),
-    )
-}
-
-async fn chart_search_handler(
-    State(hashmaps): State<Arc<RwLock<ChartsHashMaps>>>,
-    Path((apt_id, chart_search)): Path<(String, String)>,
-) -> Response {
-    if let Some(charts) = lookup_charts(&apt_id.to_uppercase(), &hashmaps) {
-        if let Some(chart) = charts
-            .iter()
-            .find(|c| c.chart_name.contains(&chart_search.to_uppercase()))
-        {
-            return Redirect::temporary(&chart.pdf_path).into_response();
-        } else {
-            let cleaned_search: String =
-                chart_search.chars().filter(|c| c.is_alphabetic()).collect();
-            if let Some(chart) = charts.iter().find(|c| {
-                (c.chart_group == ChartGroup::Arrivals || c.chart_group == ChartGroup::Departures)
-                    && c.chart_name.contains(&cleaned_search.to_uppercase())
-            }) {
-                return Redirect::temporary(&chart.pdf_path).into_response();
-            }
-        }
-    }
-
-    // Return 404 if we didn't find a chart above
+    error!("Error while loading cycle {cycle}: {e:#}");
     (
-        StatusCode::NOT_FOUND,
+        StatusCode::INTERNAL_SERVER_ERROR,
         Json(ErrorMessage {
             status: "error",
-            status_code: "404",
-            message: "Chart not found.",
+            status_code: "500",
+            message: "Could not read the charts of that cycle.",
         }),
     )
         .into_response()
 }
 
-const GROUP_1_TYPES: [ChartGroup; 5] = [
-    ChartGroup::Apd,
-    ChartGroup::General,
-    ChartGroup::Departures,
-    ChartGroup::Arrivals,
-    ChartGroup::Approaches,
-];
-const GROUP_2_TYPES: [ChartGroup; 1] = [ChartGroup::Apd];
-const GROUP_3_TYPES: [ChartGroup; 2] = [ChartGroup::Apd, ChartGroup::General];
-const GROUP_4_TYPES: [ChartGroup; 1] = [ChartGroup::Departures];
-const GROUP_5_TYPES: [ChartGroup; 1] = [ChartGroup::Arrivals];
-const GROUP_6_TYPES: [ChartGroup; 1] = [ChartGroup::Approaches];
-const GROUP_7_TYPES: [ChartGroup; 3] = [
-    ChartGroup::Departures,
-    ChartGroup::Arrivals,
-    ChartGroup::Approaches,
-];
-
-fn apply_group_param(charts: &[ChartDto], group: Option<i32>) -> ResponseDto {
-    group.map_or_else(
-        || Charts(charts.to_owned()),
-        |i| match i {
-            1 => filter_group_by_types(charts, &GROUP_1_TYPES, true),
-            2 => filter_group_by_types(charts, &GROUP_2_TYPES, false),
-            3 => filter_group_by_types(charts, &GROUP_3_TYPES, false),
-            4 => filter_group_by_types(charts, &GROUP_4_TYPES, false),
-            5 => filter_group_by_types(charts, &GROUP_5_TYPES, false),
-            6 => filter_group_by_types(charts, &GROUP_6_TYPES, false),
-            7 => filter_group_by_types(charts, &GROUP_7_TYPES, true),
-            _ => Charts(vec![]),
-        },
-    )
+/// Every airport's charts as one list in request order, each chart keeping its airport fields.
+/// Grouped charts are listed group by group.
+fn flatten_charts(results: ChartsResponseDto) -> Vec<ChartDto> {
+    results
+        .airports
+        .into_values()
+        .flat_map(|response| match response {
+            AirportResponseDto::Charts(response) => response.into_charts(),
+            // Not built when flattening, as they hoist the airport fields out of the charts
+            AirportResponseDto::WithAirport(_) | AirportResponseDto::Expanded(_) => Vec::new(),
+        })
+        .collect()
 }
 
-fn filter_group_by_types(
-    charts: &[ChartDto],
-    types: &[ChartGroup],
-    return_groups: bool,
-) -> ResponseDto {
-    if return_groups {
-        let mut grouped = GroupedChartsDto::new();
-        charts
-            .iter()
-            .filter(|c| types.contains(&c.chart_group))
-            .for_each(|c| grouped.add_chart(c.clone()));
-        GroupedCharts(grouped)
-    } else {
-        Charts(
-            charts
-                .iter()
-                .filter(|c| types.contains(&c.chart_group))
-                .cloned()
-                .collect(),
-        )
+/// Validates a charts request as GET does and answers with its status and headers, but without
+/// building the charts, so monitors can check the endpoint cheaply. Past cycles are loaded as for
+/// GET to check they exist.
+async fn charts_head_handler(
+    state: State<ServerState>,
+    headers: HeaderMap,
+    uri: Uri,
+    options: Result<Query<ChartsOptions>, QueryRejection>,
+    format: Result<Query<JsonFormat>, QueryRejection>,
+) -> Response {
+    let Ok(Query(chart_options)) = &options else {
+        return invalid_charts_query(&uri);
+    };
+    if format.is_err() {
+        return invalid_query();
     }
+    if chart_options.cycle.is_some() {
+        return charts_handler(state, headers, uri, options, format).await;
+    }
+    if let Some(response) = reject_charts_options(chart_options) {
+        return response;
+    }
+    let cycle = state
+        .app_state
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .cycle
+        .clone();
+    with_cycle_header(
+        (StatusCode::OK, [(header::CONTENT_TYPE, "application/json")]).into_response(),
+        &cycle,
+    )
 }
 
-async fn load_charts(current_cycle: &str) -> Result<ChartsHashMaps, anyhow::Error> {
-    debug!("Starting charts metafile request");
-    let base_url = cycle_url(current_cycle);
-    let metafile = reqwest::get(format!("{base_url}/xml_data/d-tpp_Metafile.xml"))
-        .await?
-        .text()
-        .await?;
-    debug!("Charts metafile request completed");
-    let dtpp = from_str::<DigitalTpp>(&metafile)?;
-
-    let eff_start =
-        NaiveDateTime::parse_from_str(&dtpp.from_effective_date, "%H%MZ %m/%d/%y")?.and_utc();
-    let now = Utc::now();
-    debug!("Effective start for charts: {}", eff_start);
-    if eff_start > now {
-        anyhow::bail!("Effective date {} greater than now {}", eff_start, now);
-    }
-
-    let mut faa: IndexMap<String, Vec<ChartDto>> = IndexMap::new();
-    let mut icao: IndexMap<String, String> = IndexMap::new();
-    let mut count = 0;
-
-    for state in dtpp.states {
-        for city in state.cities {
-            for airport in city.airports {
-                for record in airport
-                    .chart_records
-                    .into_iter()
-                    .filter(|r| r.useraction != "D")
-                {
-                    let chart_dto = ChartDto {
-                        state: state.id.clone(),
-                        state_full: state.full_name.clone(),
-                        city: city.id.clone(),
-                        volume: city.volume.clone(),
-                        airport_name: airport.id.clone(),
-                        military: airport.military.clone(),
-                        faa_ident: airport.apt_ident.clone(),
-                        icao_ident: airport.icao_ident.clone(),
-                        chart_seq: record.chartseq,
-                        chart_name: record.chart_name,
-                        pdf_path: format!("{base_url}/{pdf}", pdf = record.pdf_name),
-                        chart_group: match record.chart_code.as_str() {
-                            "IAP" => ChartGroup::Approaches,
-                            "ODP" | "DP" | "DAU" => ChartGroup::Departures,
-                            "STAR" => ChartGroup::Arrivals,
-                            "APD" => ChartGroup::Apd,
-                            _ => ChartGroup::General, // Includes "MIN" | "LAH" | "HOT"
-                        },
-                        chart_code: record.chart_code,
-                        pdf_name: record.pdf_name,
-                    };
-
-                    if !chart_dto.icao_ident.is_empty() {
-                        icao.insert(chart_dto.icao_ident.clone(), chart_dto.faa_ident.clone());
-                    }
-
-                    // Prefer the syntax below, but requires a clone in the modify case
-                    // faa.entry(chart_dto.faa_ident.clone())
-                    //     .and_modify(|charts| charts.push(chart_dto.clone()))
-                    //     .or_insert(vec![chart_dto]);
-
-                    if let Some(charts) = faa.get_mut(&chart_dto.faa_ident) {
-                        charts.push(chart_dto);
-                    } else {
-                        faa.insert(chart_dto.faa_ident.clone(), vec![chart_dto]);
-                    }
+/// Adds the `X-Cycle` header naming the cycle the charts were served from
+fn with_cycle_header(mut response: Response, cycle: &str) -> Response {
+    if let Ok(cycle) = HeaderValue::from_str(cycle) {
+        response.headers_mut().insert("x-cycle", cycle);
+    }
+    response
+}
 
-                    count += 1;
-                }
+/// Attaches enrichment details to the airport metadata of each airport that has it
+fn enrich_airports(results: &mut ChartsResponseDto, enricher: &dyn AirportEnricher) {
+    for response in results.airports.values_mut() {
+        match response {
+            AirportResponseDto::WithAirport(charts) => {
+                charts.airport.info = enricher.airport_info(&charts.airport.faa_ident);
             }
+            AirportResponseDto::Expanded(charts) => {
+                charts.airport.info = enricher.airport_info(&charts.airport.ident);
+            }
+            AirportResponseDto::Charts(_) => {}
+        }
+    }
+}
+
+/// The configured enrichment source, if any. An unreadable source only disables enrichment.
+fn load_enricher(config: &Config) -> Option<Arc<dyn AirportEnricher>> {
+    let path = config.airport_info_file.as_deref()?;
+    match FileAirportEnricher::load(path) {
+        Ok(enricher) => {
+            info!(
+                "Loaded airport info for {} airports from {}",
+                enricher.airport_count(),
+                path.display()
+            );
+            Some(Arc::new(enricher))
+        }
+        Err(e) => {
+            warn!("Could not load airport info from {}: {e}", path.display());
+            None
         }
     }
+}
 
-    info!("Loaded {count} charts");
-    Ok(ChartsHashMaps { faa, icao })
+/// The configured chart name aliases, falling back to the built-in ones if unset or unreadable
+fn load_chart_aliases(config: &Config) -> ChartAliases {
+    let Some(path) = config.chart_aliases_file.as_deref() else {
+        return ChartAliases::default();
+    };
+    match ChartAliases::load(path) {
+        Ok(aliases) => {
+            info!(
+                "Loaded {} chart aliases from {}",
+                aliases.alias_count(),
+                path.display()
+            );
+            aliases
+        }
+        Err(e) => {
+            warn!("Could not load chart aliases from {}: {e}", path.display());
+            ChartAliases::default()
+        }
+    }
 }
 
-async fn fetch_current_cycle() -> Result<String, anyhow::Error> {
-    info!("Fetching current cycle");
-    let cycle_xml = reqwest::get("https://external-api.faa.gov/apra/dtpp/info")
-        .await?
-        .text()
-        .await?;
-    let product_set = from_str::<ProductSet>(&cycle_xml)?;
-    let date = NaiveDate::parse_from_str(&product_set.edition.date, "%m/%d/%Y")?;
-    let cycle_str = format!("{}{}", date.format("%y"), product_set.edition.number);
-    info!("Found current cycle: {cycle_str}");
-    Ok(cycle_str)
+/// The pre-warmed response for a hot airport requested without any options, which would build
+/// the same response
+fn prewarmed_response(
+    app_state: &RwLock<AppState>,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> Option<Response> {
+    let ident = uri.query()?.strip_prefix("apt=")?;
+    if accept_profile(headers).is_some() {
+        return None;
+    }
+    let (body, cycle) = {
+        let reader = app_state.read().unwrap_or_else(PoisonError::into_inner);
+        (reader.prewarmed.get(ident).cloned()?, reader.cycle.clone())
+    };
+    debug!("Serving pre-warmed response for {ident}");
+    Some(with_cycle_header(
+        (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            body,
+        )
+            .into_response(),
+        &cycle,
+    ))
 }
 
-fn cycle_url(current_cycle: &str) -> String {
-    format!("https://aeronav.faa.gov/d-tpp/{current_cycle}",)
+/// Builds and serializes the response without options for each hot airport once charts are
+/// loaded, so the first requests after a swap skip that work. The result is dropped if the charts
+/// were replaced again in the meantime.
+async fn prewarm_responses(app_state: Arc<RwLock<AppState>>, hot_airports: Vec<String>) {
+    if hot_airports.is_empty() {
+        app_state
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .prewarmed = Arc::default();
+        return;
+    }
+    let (cycle, hashmaps) = {
+        let reader = app_state.read().unwrap_or_else(PoisonError::into_inner);
+        (reader.cycle.clone(), Arc::clone(&reader.hashmaps))
+    };
+    let prewarmed = {
+        let cycle = cycle.clone();
+        let hashmaps = Arc::clone(&hashmaps);
+        tokio::task::spawn_blocking(move || {
+            hot_airports
+                .into_iter()
+                .filter(|ident| hashmaps.faa_ident(ident).is_some())
+                .filter_map(|ident| {
+                    // Parsed like a request so the options match one without any
+                    let options =
+                        serde_urlencoded::from_str::<ChartsOptions>(&format!("apt={ident}"))
+                            .ok()?;
+                    let results = build_charts_response(&hashmaps, &options, &cycle, None);
+                    let body = serde_json::to_vec(&results).ok()?;
+                    Some((ident, Bytes::from(body)))
+                })
+                .collect::<HashMap<_, _>>()
+        })
+        .await
+    };
+    let prewarmed = match prewarmed {
+        Ok(prewarmed) => prewarmed,
+        Err(e) => {
+            warn!("Error while pre-warming responses for cycle {cycle}: {e}");
+            return;
+        }
+    };
+
+    let mut writer = app_state.write().unwrap_or_else(PoisonError::into_inner);
+    if Arc::ptr_eq(&writer.hashmaps, &hashmaps) {
+        info!(
+            "Pre-warmed responses for {} airports of cycle {cycle}",
+            prewarmed.len()
+        );
+        writer.prewarmed = Arc::new(prewarmed);
+    }
+}
+
+/// Path prefix for links to our own routes: the configured base path, or else the prefix the
+/// request was forwarded with if a trusted proxy sets it
+fn links_base(config: &Config, headers: &HeaderMap) -> String {
+    if !config.base_path.is_empty() {
+        config.base_path.clone()
+    } else if config.trust_forwarded_prefix {
+        forwarded_prefix(headers)
+    } else {
+        String::new()
+    }
+}
+
+/// Path prefix a reverse proxy mounted us under, from `X-Forwarded-Prefix`, so links built from it
+/// resolve through the proxy. Empty when served at the root, or when the prefix isn't a plain
+/// absolute path, as a protocol-relative `//host` or a URL with a scheme would point links
+/// elsewhere.
+fn forwarded_prefix(headers: &HeaderMap) -> String {
+    headers
+        .get("x-forwarded-prefix")
+        .and_then(|prefix| prefix.to_str().ok())
+        .map(|prefix| prefix.trim().trim_end_matches('/'))
+        .filter(|prefix| {
+            prefix.starts_with('/')
+                && !prefix.starts_with("//")
+                && !prefix.contains([':', '\\', '?', '#'])
+        })
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Links for a chart under `base`, addressing it by its exact name. The PDF link streams through
+/// the query string search, as chart names may contain a `/`.
+fn chart_links(base: &str, chart: &ChartDto) -> ChartLinksDto {
+    let charts = format!("{base}/v1/charts/{ident}", ident = chart.faa_ident);
+    let name = serde_urlencoded::to_string([("name", &chart.chart_name)])
+        .expect("Chart names always encode");
+    let term = serde_urlencoded::to_string([("term", &chart.chart_name)])
+        .expect("Chart names always encode");
+    ChartLinksDto {
+        chart: format!("{charts}/exact?{name}"),
+        pdf: format!("{charts}/search?{term}&pdf=true"),
+        charts,
+    }
+}
+
+/// Earliest amendment date within `cycles` cycles before `today`. Windows reaching back past the
+/// earliest representable date keep every dated chart rather than overflowing.
+fn amended_since(today: NaiveDate, cycles: u32) -> NaiveDate {
+    TimeDelta::try_days(CYCLE_DAYS * i64::from(cycles))
+        .and_then(|window| today.checked_sub_signed(window))
+        .unwrap_or(NaiveDate::MIN)
+}
+
+/// Builds the response for each requested airport, adding chart links under `links_base` if set
+fn build_charts_response(
+    hashmaps: &ChartsHashMaps,
+    chart_options: &ChartsOptions,
+    cycle: &str,
+    links_base: Option<&str>,
+) -> ChartsResponseDto {
+    let amended_since = chart_options
+        .amended_within
+        .map(|cycles| amended_since(Utc::now().date_naive(), cycles));
+
+    let mut results = ChartsResponseDto {
+        airports: IndexMap::new(),
+        empty: (chart_options.empty == EmptyResults::List).then(Vec::new),
+        truncated: chart_options.per_airport_limit.map(|_| Vec::new()),
+        without_icao: (chart_options.key == ResultKey::Icao).then(Vec::new),
+        debug: chart_options.debug.then(|| DebugDto {
+            cycle: cycle.to_string(),
+            airports: IndexMap::new(),
+        }),
+    };
+    for airport in chart_options.apt.as_deref().unwrap_or_default().split(',') {
+        let airport_uppercase = airport.to_uppercase();
+        let charts = hashmaps.lookup(&airport_uppercase);
+        let (key, icao_fallback) = result_key(airport, charts.as_deref(), chart_options);
+        if let Some(without_icao) = results.without_icao.as_mut().filter(|_| icao_fallback) {
+            without_icao.push(key.clone());
+        }
+        let Some(charts) = charts else {
+            if let Some(debug) = results.debug.as_mut() {
+                debug.airports.insert(
+                    key,
+                    AirportDebugDto {
+                        lookup: LookupPath::NotFound,
+                        charts_before_filtering: 0,
+                        charts_after_filtering: 0,
+                        prewarmed: false,
+                    },
+                );
+            }
+            continue;
+        };
+
+        let charts_before_filtering = charts.len();
+        // The expanded airport supersedes `airport_meta`, as both replace the same chart fields
+        let expanded_airport = (chart_options.expand == Some(Expand::Airport))
+            .then(|| charts.first().map(ExpandedAirportDto::from_chart))
+            .flatten();
+        let airport_meta = (chart_options.airport_meta && expanded_airport.is_none())
+            .then(|| charts.first().map(AirportMetaDto::from_chart))
+            .flatten();
+        let (response, truncated) =
+            build_airport_response(charts, chart_options, amended_since, links_base);
+        if let Some(truncated_airports) = results.truncated.as_mut().filter(|_| truncated) {
+            truncated_airports.push(key.clone());
+        }
+        if let Some(debug) = results.debug.as_mut() {
+            debug.airports.insert(
+                key.clone(),
+                AirportDebugDto {
+                    lookup: hashmaps.lookup_path(&airport_uppercase),
+                    charts_before_filtering,
+                    charts_after_filtering: response.chart_count(),
+                    prewarmed: false,
+                },
+            );
+        }
+        match results.empty.as_mut() {
+            Some(empty) if response.is_empty() => empty.push(key),
+            _ => {
+                let response = match (expanded_airport, airport_meta) {
+                    (Some(airport), _) => with_expanded_airport(airport, &response, chart_options),
+                    (None, Some(airport)) => with_airport_meta(airport, &response, chart_options),
+                    (None, None) => AirportResponseDto::Charts(response),
+                };
+                results.airports.insert(key, response);
+            }
+        }
+    }
+    results
+}
+
+/// Ident keying an airport's results, either as requested or canonicalized by `key`, and whether
+/// `key=icao` fell back to the FAA ident for want of an ICAO one
+fn result_key(
+    airport: &str,
+    charts: Option<&[ChartDto]>,
+    chart_options: &ChartsOptions,
+) -> (String, bool) {
+    let (ident, icao_fallback) = match (chart_options.key, charts.and_then(<[ChartDto]>::first)) {
+        (ResultKey::Faa, Some(chart)) => (chart.faa_ident.as_str(), false),
+        (ResultKey::Icao, Some(chart)) => chart
+            .icao_ident
+            .as_deref()
+            .filter(|icao| !icao.is_empty())
+            .map_or((chart.faa_ident.as_str(), true), |icao| (icao, false)),
+        _ => (airport, false),
+    };
+    let ident = match chart_options.key_case {
+        KeyCase::Upper => ident.to_uppercase(),
+        KeyCase::Lower => ident.to_lowercase(),
+    };
+    (ident, icao_fallback)
+}
+
+#[derive(Deserialize)]
+struct RawGroupOption {
+    group: Option<String>,
+}
+
+/// Explains a query that doesn't deserialize, singling out non-integer group codes
+fn invalid_charts_query(uri: &Uri) -> Response {
+    let invalid_group = Query::<RawGroupOption>::try_from_uri(uri)
+        .ok()
+        .and_then(|Query(raw)| raw.group)
+        .is_some_and(|group| group.parse::<i32>().is_err());
+    if invalid_group {
+        return invalid_group_code();
+    }
+    invalid_query()
+}
+
+fn invalid_query() -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorMessage {
+            status: "error",
+            status_code: "400",
+            message: "Invalid query parameters.",
+        }),
+    )
+        .into_response()
+}
+
+fn invalid_body() -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorMessage {
+            status: "error",
+            status_code: "400",
+            message: "Invalid request body.",
+        }),
+    )
+        .into_response()
+}
+
+/// Rejects requests without an airport or with an unknown group code or malformed runway
+fn reject_charts_options(chart_options: &ChartsOptions) -> Option<Response> {
+    // Check that we have an airport to lookup
+    if chart_options.apt.is_none()
+        || chart_options
+            .apt
+            .as_ref()
+            .is_some_and(|s| s.trim().is_empty())
+    {
+        return Some(
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorMessage {
+                    status: "error",
+                    status_code: "404",
+                    message: "Please specify an airport.",
+                }),
+            )
+                .into_response(),
+        );
+    }
+
+    // Check if supplied chart group is valid, if given as param
+    if chart_options
+        .group
+        .is_some_and(|i| group_types(i).is_none())
+    {
+        return Some(invalid_group_code());
+    }
+
+    if chart_options
+        .runway
+        .as_deref()
+        .is_some_and(|runway| parse_runway(runway).is_none())
+    {
+        return Some(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorMessage {
+                    status: "error",
+                    status_code: "400",
+                    message: "That is not a valid runway.",
+                }),
+            )
+                .into_response(),
+        );
+    }
+    None
+}
+
+/// Wraps the charts in the envelope carrying the shared airport fields once
+fn with_airport_meta(
+    mut airport: AirportMetaDto,
+    response: &ResponseDto,
+    chart_options: &ChartsOptions,
+) -> AirportResponseDto {
+    if !chart_options.include_icao {
+        airport.icao_ident = None;
+    }
+    let mut charts = serde_json::to_value(response).expect("Chart responses always serialize");
+    strip_airport_meta(&mut charts);
+    AirportResponseDto::WithAirport(AirportChartsDto { airport, charts })
+}
+
+/// Wraps the charts in the envelope embedding their airport as a structured object
+fn with_expanded_airport(
+    mut airport: ExpandedAirportDto,
+    response: &ResponseDto,
+    chart_options: &ChartsOptions,
+) -> AirportResponseDto {
+    if !chart_options.include_icao {
+        airport.icao = None;
+    }
+    let mut charts = serde_json::to_value(response).expect("Chart responses always serialize");
+    strip_airport_meta(&mut charts);
+    AirportResponseDto::Expanded(ExpandedAirportChartsDto { airport, charts })
+}
+
+/// Removes the hoisted airport fields from every chart object, whatever the grouping shape
+fn strip_airport_meta(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) if object.contains_key("chart_name") => {
+            for field in AirportMetaDto::FIELDS {
+                object.remove(field);
+            }
+        }
+        serde_json::Value::Object(object) => object.values_mut().for_each(strip_airport_meta),
+        serde_json::Value::Array(values) => values.iter_mut().for_each(strip_airport_meta),
+        _ => {}
+    }
+}
+
+/// Returns the airport's charts in the requested shape, along with whether `per_airport_limit`
+/// dropped any
+fn build_airport_response(
+    mut charts: Vec<ChartDto>,
+    chart_options: &ChartsOptions,
+    amended_since: Option<NaiveDate>,
+    links_base: Option<&str>,
+) -> (ResponseDto, bool) {
+    // Charts without a parseable amendment date are excluded when filtering
+    if let Some(amended_since) = amended_since {
+        charts.retain(|c| {
+            c.parsed_amendment_date()
+                .is_some_and(|d| d >= amended_since)
+        });
+    }
+    // Charts without a parseable amendment number are likewise excluded
+    if let Some(min_amendment_num) = chart_options.min_amendment_num {
+        let unparseable = charts
+            .iter()
+            .filter(|c| c.parsed_amendment_number().is_none())
+            .count();
+        if let Some(chart) = charts.first().filter(|_| unparseable > 0) {
+            debug!(
+                "Excluded {unparseable} charts of {} without a parseable amendment number",
+                chart.faa_ident
+            );
+        }
+        charts.retain(|c| {
+            c.parsed_amendment_number()
+                .is_some_and(|n| n >= min_amendment_num)
+        });
+    }
+    // Domestic clients can drop the ICAO ident to save payload
+    if !chart_options.include_icao {
+        for chart in &mut charts {
+            chart.icao_ident = None;
+        }
+    }
+    if let Some(base) = links_base {
+        for chart in &mut charts {
+            chart.links = Some(chart_links(base, chart));
+        }
+    }
+    // Matches any runway a chart covers, so `4L` and `22R` both match "ILS RWY 4L/22R"
+    if let Some(runway) = chart_options.runway.as_deref().and_then(parse_runway) {
+        charts.retain(|c| c.runways().contains(&runway));
+    }
+    // Sorted before grouping, as groups keep the order charts are added in. The sort is stable, so
+    // charts with equal sequences stay in metafile order in both flat and grouped output.
+    match chart_options.order {
+        ChartOrder::Metafile => {}
+        ChartOrder::Seq => charts.sort_by(|a, b| a.chart_seq_key().cmp(&b.chart_seq_key())),
+        ChartOrder::Book => charts.sort_by(|a, b| a.book_order_key().cmp(&b.book_order_key())),
+    }
+    // Limited after filtering and sorting but before grouping, so every shape keeps the same charts
+    let mut truncated = false;
+    if let Some(limit) = chart_options.per_airport_limit {
+        if let Some(types) = chart_options.group.and_then(group_types) {
+            charts.retain(|c| types.contains(&c.chart_group));
+        }
+        truncated = charts.len() > limit;
+        charts.truncate(limit);
+    }
+
+    let response = match chart_options.shape {
+        ResponseShape::Single => format_groups(
+            apply_group_param(&charts, chart_options.group),
+            chart_options,
+        ),
+        // Both shapes hold the same charts: those of the `group` code, or every chart without one
+        ResponseShape::Both => {
+            let types = chart_options
+                .group
+                .and_then(group_types)
+                .unwrap_or(&GROUP_1_TYPES);
+            Both(BothShapesDto {
+                charts: charts
+                    .iter()
+                    .filter(|c| types.contains(&c.chart_group))
+                    .cloned()
+                    .collect(),
+                grouped: Box::new(format_groups(
+                    filter_group_by_types(
+                        &charts,
+                        types,
+                        if splits_apd(chart_options.group) {
+                            GroupOutput::ByChartGroup
+                        } else {
+                            GroupOutput::Grouped
+                        },
+                    ),
+                    chart_options,
+                )),
+            })
+        }
+    };
+    (response, truncated)
+}
+
+/// Applies the `group_empty` and `group_format` options to grouped output
+fn format_groups(response: ResponseDto, chart_options: &ChartsOptions) -> ResponseDto {
+    match response {
+        GroupedCharts(mut grouped) => {
+            if chart_options.group_empty == GroupEmpty::Include {
+                grouped.include_empty_groups(splits_apd(chart_options.group));
+            }
+            match (chart_options.group_format, chart_options.naming) {
+                (GroupFormat::Keys, GroupNaming::Faa) => GroupedCharts(grouped),
+                (GroupFormat::Keys, GroupNaming::Snake) => SnakeCaseGroups(grouped.into()),
+                (GroupFormat::Labeled, _) => LabeledGroups(grouped.into_labeled()),
+            }
+        }
+        response => response,
+    }
+}
+
+async fn cycle_handler(State(app_state): State<Arc<RwLock<AppState>>>) -> Response {
+    let reader = app_state.read().unwrap_or_else(PoisonError::into_inner);
+    (
+        StatusCode::OK,
+        Json(CycleDto {
+            cycle: reader.cycle.clone(),
+            from_effective_date: reader.from_effective_date,
+            to_effective_date: reader.to_effective_date,
+            loaded_at: reader.loaded_at,
+            chart_counts: reader.hashmaps.counts.clone(),
+            edition: reader.edition.clone(),
+        }),
+    )
+        .into_response()
+}
+
+/// Returns the exact metafile the current cycle was parsed from, so operators can diff our output
+/// against the source. Hidden unless an admin token is configured, which must then be presented as
+/// a bearer token.
+async fn metafile_handler(State(state): State<ServerState>, headers: HeaderMap) -> Response {
+    if let Some(response) = reject_non_admin(&state.config.get(), &headers) {
+        return response;
+    }
+
+    let metafile = state
+        .app_state
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .metafile
+        .clone();
+    metafile.map_or_else(
+        || StatusCode::NOT_FOUND.into_response(),
+        |xml| {
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "application/xml")],
+                xml,
+            )
+                .into_response()
+        },
+    )
+}
+
+/// Returns the loaded charts as a `SQLite` database for querying with SQL, built afresh on each
+/// request. Admin routes as for the metafile.
+async fn sqlite_export_handler(State(state): State<ServerState>, headers: HeaderMap) -> Response {
+    if let Some(response) = reject_non_admin(&state.config.get(), &headers) {
+        return response;
+    }
+
+    let (cycle, hashmaps) = {
+        let reader = state
+            .app_state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        (reader.cycle.clone(), Arc::clone(&reader.hashmaps))
+    };
+    let database = tokio::task::spawn_blocking(move || sqlite_export::export(&hashmaps.faa))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|database| Ok(database?));
+    match database {
+        Ok(database) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, "application/vnd.sqlite3".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"charts-{cycle}.sqlite\""),
+                ),
+            ],
+            database,
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Error while exporting cycle {cycle} to SQLite: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorMessage {
+                    status: "error",
+                    status_code: "500",
+                    message: "Could not export the charts.",
+                }),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Hides admin routes with a 404 unless an admin token is configured, which must then be
+/// presented as a bearer token
+fn reject_non_admin(config: &Config, headers: &HeaderMap) -> Option<Response> {
+    let Some(token) = config.admin_token.as_deref() else {
+        return Some(StatusCode::NOT_FOUND.into_response());
+    };
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    (bearer != Some(token)).then(|| {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorMessage {
+                status: "error",
+                status_code: "401",
+                message: "Missing or invalid admin token.",
+            }),
+        )
+            .into_response()
+    })
+}
+
+/// Replaces the log filter with `RUST_LOG`-style directives such as `chartsapi_rs=trace,info`
+/// until the next restart or SIGHUP, answering with the filter applied
+async fn log_filter_handler(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    body: Result<Json<LogFilterDto>, JsonRejection>,
+) -> Response {
+    if let Some(response) = reject_non_admin(&state.config.get(), &headers) {
+        return response;
+    }
+
+    let Some(filter) = body
+        .ok()
+        .and_then(|Json(body)| EnvFilter::try_new(body.filter).ok())
+    else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorMessage {
+                status: "error",
+                status_code: "400",
+                message: "That is not a valid log filter.",
+            }),
+        )
+            .into_response();
+    };
+    let applied = filter.to_string();
+    if let Err(e) = state.log_filter.reload(filter) {
+        warn!("Could not set log filter: {e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    info!("Log filter set to {applied:?} through the admin endpoint");
+    (StatusCode::OK, Json(LogFilterDto { filter: applied })).into_response()
+}
+
+/// Aggregate counts for status pages, all computed when the cycle was loaded
+async fn stats_handler(State(app_state): State<Arc<RwLock<AppState>>>) -> Response {
+    let reader = app_state.read().unwrap_or_else(PoisonError::into_inner);
+    (
+        StatusCode::OK,
+        Json(StatsDto {
+            cycle: reader.cycle.clone(),
+            from_effective_date: reader.from_effective_date,
+            to_effective_date: reader.to_effective_date,
+            airports: reader.hashmaps.airport_counts.clone(),
+            charts: reader.hashmaps.counts.clone(),
+        }),
+    )
+        .into_response()
+}
+
+async fn chart_codes_handler(State(app_state): State<Arc<RwLock<AppState>>>) -> Response {
+    let hashmaps = Arc::clone(
+        &app_state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .hashmaps,
+    );
+    (StatusCode::OK, Json(&hashmaps.codes)).into_response()
+}
+
+async fn groups_handler(format: Result<Query<JsonFormat>, QueryRejection>) -> Response {
+    let Ok(Query(format)) = format else {
+        return invalid_query();
+    };
+    let groups: Vec<GroupCodeDto> = GROUP_CODES
+        .iter()
+        .map(|(code, types, output)| GroupCodeDto {
+            code: *code,
+            grouped: *output != GroupOutput::Flat,
+            groups: types.to_vec(),
+        })
+        .collect();
+    json_response(&groups, format)
+}
+
+async fn airport_charts_handler(
+    State(app_state): State<Arc<RwLock<AppState>>>,
+    Path(apt_id): Path<String>,
+    format: Result<Query<JsonFormat>, QueryRejection>,
+) -> Response {
+    let Ok(Query(format)) = format else {
+        return invalid_query();
+    };
+    lookup_charts(&apt_id.to_uppercase(), &app_state)
+        .map_or_else(airport_not_found, |charts| json_response(&charts, format))
+}
+
+#[derive(Deserialize)]
+struct CsvExportOptions {
+    state: Option<String>,
+    group: Option<i32>,
+}
+
+/// Streams every loaded chart as CSV one airport at a time, optionally filtered by state and by
+/// the chart groups of a `group` code
+async fn charts_csv_handler(
+    State(app_state): State<Arc<RwLock<AppState>>>,
+    uri: Uri,
+    options: Result<Query<CsvExportOptions>, QueryRejection>,
+) -> Response {
+    let Ok(Query(options)) = options else {
+        return invalid_charts_query(&uri);
+    };
+    let types = match options.group {
+        None => None,
+        Some(group) => match group_types(group) {
+            Some(types) => Some(types),
+            None => return invalid_group_code(),
+        },
+    };
+    let state = options.state.map(|state| state.to_uppercase());
+    let hashmaps = Arc::clone(
+        &app_state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .hashmaps,
+    );
+
+    let rows = (0..hashmaps.faa.len()).map(move |index| {
+        let mut chunk = String::new();
+        for chart in &hashmaps.faa[index] {
+            if state.as_ref().is_none_or(|state| chart.state == *state)
+                && types.is_none_or(|types| types.contains(&chart.chart_group))
+            {
+                chunk.push_str(&csv_export::chart_row(chart));
+            }
+        }
+        Ok::<_, Infallible>(chunk)
+    });
+    let body = stream::iter(std::iter::once(Ok(csv_export::HEADER.to_string())).chain(rows));
+
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"charts.csv\"",
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct PdfUrlsOptions {
+    group: Option<i32>,
+    #[serde(default)]
+    with_names: bool,
+}
+
+/// The airport's most recently amended charts, all of them when several share the latest date
+async fn latest_amendment_handler(
+    State(app_state): State<Arc<RwLock<AppState>>>,
+    Path(apt_id): Path<String>,
+    format: Result<Query<JsonFormat>, QueryRejection>,
+) -> Response {
+    let Ok(Query(format)) = format else {
+        return invalid_query();
+    };
+    let Some(charts) = lookup_charts(&apt_id.to_uppercase(), &app_state) else {
+        return airport_not_found();
+    };
+    // Charts with a blank or unparseable amendment date can't be the latest
+    let Some(latest) = charts
+        .iter()
+        .filter_map(ChartDto::parsed_amendment_date)
+        .max()
+    else {
+        return chart_not_found();
+    };
+    let latest_charts: Vec<ChartDto> = charts
+        .into_iter()
+        .filter(|c| c.parsed_amendment_date() == Some(latest))
+        .collect();
+    json_response(&latest_charts, format)
+}
+
+/// Just the PDF links of an airport's charts, for clients that batch-download them
+async fn pdf_urls_handler(
+    State(app_state): State<Arc<RwLock<AppState>>>,
+    Path(apt_id): Path<String>,
+    uri: Uri,
+    options: Result<Query<PdfUrlsOptions>, QueryRejection>,
+) -> Response {
+    let Ok(Query(options)) = options else {
+        return invalid_charts_query(&uri);
+    };
+    let types = match options.group.map(group_types) {
+        None => None,
+        Some(Some(types)) => Some(types),
+        Some(None) => return invalid_group_code(),
+    };
+    let Some(charts) = lookup_charts(&apt_id.to_uppercase(), &app_state) else {
+        return airport_not_found();
+    };
+    let charts = charts
+        .into_iter()
+        .filter(|c| types.is_none_or(|types| types.contains(&c.chart_group)));
+
+    if options.with_names {
+        let links: Vec<PdfLinkDto> = charts
+            .map(|c| PdfLinkDto {
+                chart_name: c.chart_name,
+                pdf_path: c.pdf_path,
+            })
+            .collect();
+        (StatusCode::OK, Json(links)).into_response()
+    } else {
+        let urls: Vec<String> = charts.map(|c| c.pdf_path).collect();
+        (StatusCode::OK, Json(urls)).into_response()
+    }
+}
+
+/// Chart groups present at an airport with their chart counts, in the order they first appear
+async fn airport_groups_handler(
+    State(app_state): State<Arc<RwLock<AppState>>>,
+    Path(apt_id): Path<String>,
+) -> Response {
+    lookup_charts(&apt_id.to_uppercase(), &app_state).map_or_else(airport_not_found, |charts| {
+        let mut groups: IndexMap<ChartGroup, usize> = IndexMap::new();
+        for chart in charts {
+            *groups.entry(chart.chart_group).or_insert(0) += 1;
+        }
+        (StatusCode::OK, Json(groups)).into_response()
+    })
+}
+
+#[derive(Deserialize)]
+struct ResolveIdentsRequest {
+    idents: Vec<String>,
+}
+
+/// Resolves each ident to its canonical FAA and ICAO idents, in input order
+async fn resolve_idents_handler(
+    State(app_state): State<Arc<RwLock<AppState>>>,
+    request: Result<Json<ResolveIdentsRequest>, JsonRejection>,
+) -> Response {
+    let Ok(Json(request)) = request else {
+        return invalid_body();
+    };
+    let hashmaps = Arc::clone(
+        &app_state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .hashmaps,
+    );
+    let idents = request
+        .idents
+        .into_iter()
+        .map(|ident| {
+            let faa_ident = hashmaps.faa_ident(&ident.trim().to_uppercase());
+            let icao_ident = faa_ident
+                .and_then(|faa_id| hashmaps.faa.get(faa_id))
+                .and_then(|charts| charts.first())
+                .and_then(|chart| chart.icao_ident.clone())
+                .filter(|icao_id| !icao_id.is_empty());
+            ResolvedIdentDto {
+                found: faa_ident.is_some(),
+                faa_ident: faa_ident.map(str::to_string),
+                icao_ident,
+                ident,
+            }
+        })
+        .collect();
+    (StatusCode::OK, Json(ResolvedIdentsDto { idents })).into_response()
+}
+
+#[derive(Deserialize)]
+struct ExactChartOptions {
+    name: String,
+}
+
+async fn exact_chart_handler(
+    State(app_state): State<Arc<RwLock<AppState>>>,
+    Path(apt_id): Path<String>,
+    options: Result<Query<ExactChartOptions>, QueryRejection>,
+) -> Response {
+    let Ok(Query(options)) = options else {
+        return invalid_query();
+    };
+    let name = normalize_chart_name(&options.name);
+    lookup_charts(&apt_id.to_uppercase(), &app_state)
+        .and_then(|charts| {
+            charts
+                .into_iter()
+                .find(|c| normalize_chart_name(&c.chart_name) == name)
+        })
+        .map_or_else(chart_not_found, |chart| {
+            (StatusCode::OK, Json(chart)).into_response()
+        })
+}
+
+/// Uppercases and collapses whitespace so exact lookups tolerate casing and spacing differences
+fn normalize_chart_name(chart_name: &str) -> String {
+    chart_name
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_uppercase()
+}
+
+fn lookup_charts(apt_id: &str, app_state: &Arc<RwLock<AppState>>) -> Option<Vec<ChartDto>> {
+    app_state
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .hashmaps
+        .lookup(apt_id)
+}
+
+/// Returns the charts for `cycle`, loading past cycles on demand through the cache
+async fn load_cycle(
+    state: &ServerState,
+    cycle: &str,
+) -> Result<Arc<ChartsHashMaps>, anyhow::Error> {
+    {
+        let reader = state
+            .app_state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if reader.cycle == cycle {
+            return Ok(Arc::clone(&reader.hashmaps));
+        }
+    }
+    if let Some(hashmaps) = state.cache.get(cycle).await {
+        return Ok(hashmaps);
+    }
+
+    info!("Loading charts for cycle {cycle} on demand");
+    let hashmaps = load_charts(&state.client, &state.config.get(), cycle)
+        .await?
+        .hashmaps;
+    state.cache.put(cycle, Arc::clone(&hashmaps)).await;
+    Ok(hashmaps)
+}
+
+#[derive(Deserialize)]
+struct SuggestOption {
+    #[serde(default)]
+    suggest: bool,
+}
+
+async fn chart_search_handler(
+    State(state): State<ServerState>,
+    Path((apt_id, chart_search)): Path<(String, String)>,
+    options: Result<Query<SuggestOption>, QueryRejection>,
+) -> Response {
+    let Ok(Query(options)) = options else {
+        return invalid_query();
+    };
+    // A trailing `.pdf` streams the chart instead of redirecting to it
+    let (chart_search, stream_pdf) = strip_pdf_extension(&chart_search);
+    serve_chart_search(&state, &apt_id, chart_search, stream_pdf, options.suggest).await
+}
+
+/// Existence check for the chart search, answering with the chart's PDF URL in `Location`
+/// instead of redirecting to or streaming it
+async fn chart_search_head_handler(
+    State(state): State<ServerState>,
+    Path((apt_id, chart_search)): Path<(String, String)>,
+) -> Response {
+    let (chart_search, _) = strip_pdf_extension(&chart_search);
+    lookup_charts(&apt_id.to_uppercase(), &state.app_state)
+        .and_then(|charts| find_chart(&charts, &state.aliases, chart_search).cloned())
+        .map_or_else(
+            || StatusCode::NOT_FOUND.into_response(),
+            |chart| (StatusCode::OK, [(header::LOCATION, chart.pdf_path)]).into_response(),
+        )
+}
+
+#[derive(Deserialize)]
+struct ChartSearchOptions {
+    #[serde(alias = "q")]
+    term: String,
+    #[serde(default)]
+    pdf: bool,
+    #[serde(default)]
+    suggest: bool,
+}
+
+/// Query string form of the chart search, for terms with characters that clients or proxies
+/// mangle in a path segment, such as the `/` in "ILS RWY 4/22"
+async fn chart_search_query_handler(
+    State(state): State<ServerState>,
+    Path(apt_id): Path<String>,
+    options: Result<Query<ChartSearchOptions>, QueryRejection>,
+) -> Response {
+    let Ok(Query(options)) = options else {
+        return invalid_query();
+    };
+    serve_chart_search(&state, &apt_id, &options.term, options.pdf, options.suggest).await
+}
+
+async fn serve_chart_search(
+    state: &ServerState,
+    apt_id: &str,
+    chart_search: &str,
+    stream_pdf: bool,
+    suggest: bool,
+) -> Response {
+    // Several airports can't share a redirect, so their chart URLs are listed instead
+    if apt_id.contains(',') {
+        let urls: IndexMap<String, Option<String>> = apt_id
+            .split(',')
+            .map(|airport| {
+                let airport = airport.trim().to_uppercase();
+                let url = lookup_charts(&airport, &state.app_state).and_then(|charts| {
+                    find_chart(&charts, &state.aliases, chart_search).map(|c| c.pdf_path.clone())
+                });
+                (airport, url)
+            })
+            .collect();
+        return (StatusCode::OK, Json(urls)).into_response();
+    }
+
+    let Some(charts) = lookup_charts(&apt_id.to_uppercase(), &state.app_state) else {
+        if matches_deleted_chart(state, apt_id, chart_search) {
+            return chart_gone();
+        }
+        return chart_not_found();
+    };
+    let Some(chart) = find_chart(&charts, &state.aliases, chart_search) else {
+        if matches_deleted_chart(state, apt_id, chart_search) {
+            return chart_gone();
+        }
+        if suggest {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ChartSuggestionsDto {
+                    error: ErrorMessage {
+                        status: "error",
+                        status_code: "404",
+                        message: "Chart not found.",
+                    },
+                    suggestions: suggest_charts(&charts, chart_search),
+                }),
+            )
+                .into_response();
+        }
+        return chart_not_found();
+    };
+
+    // Locally mirrored charts are always served directly, as the FAA may not be reachable
+    if stream_pdf || local_pdf_path(state, chart).is_some() {
+        proxy_chart_pdf(state, chart).await
+    } else {
+        Redirect::temporary(&chart.pdf_path).into_response()
+    }
+}
+
+/// Whether the search matches one of the airport's charts deleted this cycle, for a search that
+/// matched none of its current charts
+fn matches_deleted_chart(state: &ServerState, apt_id: &str, chart_search: &str) -> bool {
+    let hashmaps = Arc::clone(
+        &state
+            .app_state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .hashmaps,
+    );
+    hashmaps
+        .lookup_deleted(&apt_id.to_uppercase())
+        .is_some_and(|charts| find_chart(charts, &state.aliases, chart_search).is_some())
+}
+
+async fn chart_pdf_handler(
+    State(state): State<ServerState>,
+    Path((apt_id, chart_search)): Path<(String, String)>,
+) -> Response {
+    let Some(chart) = lookup_charts(&apt_id.to_uppercase(), &state.app_state)
+        .and_then(|charts| find_chart(&charts, &state.aliases, &chart_search).cloned())
+    else {
+        return chart_not_found();
+    };
+
+    proxy_chart_pdf(&state, &chart).await
+}
+
+/// Streams the airport diagram, named after its `pdf_name` so saved copies keep the FAA filename
+async fn diagram_pdf_handler(
+    State(state): State<ServerState>,
+    Path(apt_id): Path<String>,
+) -> Response {
+    let Some(charts) = lookup_charts(&apt_id.to_uppercase(), &state.app_state) else {
+        return airport_not_found();
+    };
+    let Some(chart) = charts
+        .into_iter()
+        .find(|c| c.chart_group == ChartGroup::Apd)
+    else {
+        return chart_not_found();
+    };
+
+    let mut response = proxy_chart_pdf(&state, &chart).await;
+    if response.status().is_success() {
+        if let Ok(disposition) =
+            HeaderValue::from_str(&format!("inline; filename=\"{}\"", chart.pdf_name))
+        {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_DISPOSITION, disposition);
+        }
+    }
+    response
+}
+
+/// Path of the chart in the local PDF mirror, laid out as `{pdf_dir}/{cycle}/{pdf_name}`, if present
+fn local_pdf_path(state: &ServerState, chart: &ChartDto) -> Option<PathBuf> {
+    let config = state.config.get();
+    let pdf_dir = config.pdf_dir.as_ref()?;
+    let cycle = state
+        .app_state
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .cycle
+        .clone();
+    Some(pdf_dir.join(cycle).join(&chart.pdf_name)).filter(|path| path.is_file())
+}
+
+async fn proxy_chart_pdf(state: &ServerState, chart: &ChartDto) -> Response {
+    if let Some(path) = local_pdf_path(state, chart) {
+        match tokio::fs::File::open(&path).await {
+            Ok(file) => {
+                debug!("Serving chart PDF from {}", path.display());
+                return (
+                    StatusCode::OK,
+                    [(header::CONTENT_TYPE, "application/pdf")],
+                    Body::from_stream(ReaderStream::new(file)),
+                )
+                    .into_response();
+            }
+            Err(e) => warn!(
+                "Error while opening local chart PDF {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    // Redirects issued by the FAA are followed by the client's redirect policy
+    match faa_get(&state.client, &chart.pdf_path).await {
+        Ok(pdf) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/pdf")],
+            Body::from_stream(pdf.bytes_stream()),
+        )
+            .into_response(),
+        Err(e) => {
+            warn!("Error while fetching chart PDF {}: {}", chart.pdf_path, e);
+            faa_error_response(&e, "Could not fetch chart PDF from the FAA.")
+        }
+    }
+}
+
+const fn strip_pdf_extension(chart_search: &str) -> (&str, bool) {
+    let split = chart_search.len().saturating_sub(".pdf".len());
+    match chart_search.split_at_checked(split) {
+        Some((term, ext)) if ext.eq_ignore_ascii_case(".pdf") => (term, true),
+        _ => (chart_search, false),
+    }
+}
+
+/// Finds the first chart matching the search, which may chain `+`-separated fallback terms
+fn find_chart<'a>(
+    charts: &'a [ChartDto],
+    aliases: &ChartAliases,
+    chart_search: &str,
+) -> Option<&'a ChartDto> {
+    chart_search
+        .split('+')
+        .map(str::trim)
+        .filter(|term| !term.is_empty())
+        .find_map(|term| {
+            find_chart_by_alias(charts, aliases, term).or_else(|| find_chart_by_term(charts, term))
+        })
+}
+
+/// Finds the first chart whose name contains a fragment the term is an alias for
+fn find_chart_by_alias<'a>(
+    charts: &'a [ChartDto],
+    aliases: &ChartAliases,
+    term: &str,
+) -> Option<&'a ChartDto> {
+    let patterns = aliases.patterns(term)?;
+    charts.iter().find(|c| {
+        patterns
+            .iter()
+            .any(|pattern| c.chart_name.contains(pattern.as_str()))
+    })
+}
+
+/// Maximum number of chart names suggested for a search without matches
+const MAX_SUGGESTIONS: usize = 5;
+
+/// Names of the charts most similar to the search, best first, for clients to offer as
+/// alternatives. Each `+`-separated term is scored and a chart keeps its best score.
+fn suggest_charts(charts: &[ChartDto], chart_search: &str) -> Vec<String> {
+    let terms: Vec<_> = chart_search
+        .split('+')
+        .map(str::trim)
+        .filter(|term| !term.is_empty())
+        .collect();
+    let mut scored: Vec<_> = charts
+        .iter()
+        .map(|c| {
+            let score = terms
+                .iter()
+                .map(|term| similarity(term, &c.chart_name))
+                .fold(0.0, f64::max);
+            (score, &c.chart_name)
+        })
+        .filter(|(score, _)| *score > 0.0)
+        .collect();
+    // Stable, so equally similar charts stay in metafile order
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut suggestions: Vec<String> = Vec::new();
+    for (_, name) in scored {
+        if !suggestions.contains(name) {
+            suggestions.push(name.clone());
+        }
+        if suggestions.len() == MAX_SUGGESTIONS {
+            break;
+        }
+    }
+    suggestions
+}
+
+/// Dice coefficient of the character bigrams of the uppercased names, from 0 for nothing in
+/// common to 1 for the same bigrams, which tolerates typos and reordered words
+fn similarity(a: &str, b: &str) -> f64 {
+    let bigrams = |s: &str| -> Vec<(char, char)> {
+        let chars: Vec<char> = s.to_uppercase().chars().collect();
+        chars.windows(2).map(|w| (w[0], w[1])).collect()
+    };
+    let (a, mut b) = (bigrams(a), bigrams(b));
+    let total = a.len() + b.len();
+    if total == 0 {
+        return 0.0;
+    }
+    let mut shared = 0_usize;
+    for bigram in &a {
+        if let Some(index) = b.iter().position(|other| other == bigram) {
+            b.swap_remove(index);
+            shared += 1;
+        }
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let score = (2 * shared) as f64 / total as f64;
+    score
+}
+
+fn find_chart_by_term<'a>(charts: &'a [ChartDto], chart_search: &str) -> Option<&'a ChartDto> {
+    charts
+        .iter()
+        .find(|c| c.chart_name.contains(&chart_search.to_uppercase()))
+        .or_else(|| {
+            let cleaned_search: String =
+                chart_search.chars().filter(|c| c.is_alphabetic()).collect();
+            charts.iter().find(|c| {
+                (c.chart_group == ChartGroup::Arrivals || c.chart_group == ChartGroup::Departures)
+                    && c.chart_name.contains(&cleaned_search.to_uppercase())
+            })
+        })
+}
+
+fn invalid_group_code() -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        Json(ErrorMessage {
+            status: "error",
+            status_code: "403",
+            message: "That is not a valid grouping code.",
+        }),
+    )
+        .into_response()
+}
+
+fn airport_not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorMessage {
+            status: "error",
+            status_code: "404",
+            message: "Airport not found.",
+        }),
+    )
+        .into_response()
+}
+
+async fn static_not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorMessage {
+            status: "error",
+            status_code: "404",
+            message: "Static file not found.",
+        }),
+    )
+        .into_response()
+}
+
+fn chart_gone() -> Response {
+    (
+        StatusCode::GONE,
+        Json(ErrorMessage {
+            status: "error",
+            status_code: "410",
+            message: "That chart was removed in the current cycle.",
+        }),
+    )
+        .into_response()
+}
+
+fn chart_not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorMessage {
+            status: "error",
+            status_code: "404",
+            message: "Chart not found.",
+        }),
+    )
+        .into_response()
+}
+
+/// Returns the `profile` parameter of the first Accept media range that carries one, e.g.
+/// `grouped` for `Accept: application/json; profile=grouped`.
+fn accept_profile(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .flat_map(|range| range.split(';').skip(1))
+        .find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("profile")
+                .then(|| value.trim().trim_matches('"').to_ascii_lowercase())
+        })
+}
+
+async fn load_charts(
+    client: &Client,
+    config: &Config,
+    current_cycle: &str,
+) -> Result<AppState, anyhow::Error> {
+    let metafile = fetch_metafile(client, config, current_cycle).await?;
+    Ok(AppState {
+        validators: metafile.validators.clone(),
+        ..parse_charts(config, current_cycle, &metafile)?
+    })
+}
+
+/// Last-resort charts from the metafile embedded at build time, for cold starts without FAA
+/// access. Always served as stale until the refresh loop replaces them.
+#[cfg(feature = "embedded-metafile")]
+fn load_embedded_charts(config: &Config) -> Result<AppState, anyhow::Error> {
+    const EMBEDDED_METAFILE: &[u8] = include_bytes!(env!("CHARTSAPI_EMBEDDED_METAFILE"));
+    let metafile = Metafile::new(
+        Bytes::from_static(EMBEDDED_METAFILE),
+        MetafileValidators::default(),
+    );
+    let cycle = StateReader::new(&metafile.xml)?.1.cycle;
+    Ok(AppState {
+        embedded: true,
+        ..parse_charts(config, &cycle, &metafile)?
+    })
+}
+
+/// A downloaded metafile with the caching headers needed to request it again conditionally
+struct Metafile {
+    /// Exactly as downloaded, whatever its encoding
+    bytes: Bytes,
+    xml: String,
+    validators: MetafileValidators,
+}
+
+impl Metafile {
+    fn new(bytes: Bytes, validators: MetafileValidators) -> Self {
+        Self {
+            xml: faa_metafile::decode_document(&bytes),
+            bytes,
+            validators,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct MetafileValidators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl MetafileValidators {
+    fn from_headers(headers: &reqwest::header::HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            etag: header(header::ETAG),
+            last_modified: header(header::LAST_MODIFIED),
+        }
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Metafile downloads in progress by URL, so the refresh loop and on-demand loads of the same
+/// cycle share one download instead of fetching it concurrently
+static METAFILE_FETCHES: LazyLock<SingleFlight<Result<Arc<Metafile>, SharedError>>> =
+    LazyLock::new(SingleFlight::new);
+
+async fn fetch_metafile(
+    client: &Client,
+    config: &Config,
+    cycle: &str,
+) -> Result<Arc<Metafile>, anyhow::Error> {
+    let url = metafile_url(config, cycle);
+    METAFILE_FETCHES
+        .run(&url, || async {
+            match fetch_metafile_uncoalesced(client, &url, cycle, &MetafileValidators::default())
+                .await
+            {
+                Ok(Some(metafile)) => Ok(Arc::new(metafile)),
+                Ok(None) => Err(SharedError(Arc::new(anyhow::anyhow!(
+                    "metafile not modified despite an unconditional request"
+                )))),
+                Err(e) => Err(SharedError(Arc::new(e))),
+            }
+        })
+        .await
+        .map_err(anyhow::Error::new)
+}
+
+/// An error shared by every caller of a coalesced fetch. The original error is its source, so
+/// callers can still find typed errors such as [`UpstreamError`] in the chain.
+#[derive(Clone, Debug)]
+struct SharedError(Arc<anyhow::Error>);
+
+impl std::fmt::Display for SharedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SharedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&**self.0)
+    }
+}
+
+/// Fetches the metafile unless it is unchanged since the download `validators` came from
+async fn fetch_metafile_if_modified(
+    client: &Client,
+    config: &Config,
+    cycle: &str,
+    validators: &MetafileValidators,
+) -> Result<Option<Arc<Metafile>>, anyhow::Error> {
+    if validators.is_empty() {
+        fetch_metafile(client, config, cycle).await.map(Some)
+    } else {
+        Ok(
+            fetch_metafile_uncoalesced(client, &metafile_url(config, cycle), cycle, validators)
+                .await?
+                .map(Arc::new),
+        )
+    }
+}
+
+fn metafile_url(config: &Config, cycle: &str) -> String {
+    format!("{}/xml_data/d-tpp_Metafile.xml", cycle_url(config, cycle))
+}
+
+async fn fetch_metafile_uncoalesced(
+    client: &Client,
+    url: &str,
+    current_cycle: &str,
+    validators: &MetafileValidators,
+) -> Result<Option<Metafile>, anyhow::Error> {
+    debug!("Starting charts metafile request");
+    let started = Instant::now();
+    let mut request = client.get(url);
+    if let Some(etag) = &validators.etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+    }
+    let response = faa_send(request).await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        debug!("Charts metafile not modified");
+        return Ok(None);
+    }
+    let validators = MetafileValidators::from_headers(response.headers());
+    let bytes = response.bytes().await.map_err(UpstreamError::from)?;
+    debug!("Charts metafile request completed");
+    histogram!("chartsapi_load_duration_seconds", "cycle" => current_cycle.to_string(), "stage" => "fetch")
+        .record(started.elapsed());
+    Ok(Some(Metafile::new(bytes, validators)))
+}
+
+fn metafile_hash(metafile: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    metafile.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn parse_charts(
+    config: &Config,
+    current_cycle: &str,
+    metafile: &Metafile,
+) -> Result<AppState, anyhow::Error> {
+    let started = Instant::now();
+    let (states, dtpp) = StateReader::new(&metafile.xml)?;
+    debug!(
+        "Parsing metafile of cycle {} for {current_cycle}",
+        dtpp.cycle
+    );
+
+    // Without a start date we can't tell whether the cycle is effective yet, so reject it rather
+    // than risk serving pre-effective charts. The end date is informational only.
+    let eff_start =
+        parse_effective_date(&dtpp.from_effective_date).map_err(|_| UnparseableEffectiveDate {
+            cycle: current_cycle.to_string(),
+            value: dtpp.from_effective_date.clone(),
+        })?;
+    let eff_end = parse_effective_date(&dtpp.to_effective_date).unwrap_or_else(|e| {
+        warn!(
+            "Unparseable effective end date {:?} for cycle {current_cycle}, assuming a {CYCLE_DAYS} day cycle: {}",
+            dtpp.to_effective_date, e
+        );
+        eff_start + TimeDelta::days(CYCLE_DAYS)
+    });
+    let now = Utc::now();
+    debug!("Effective start for charts: {}", eff_start);
+    if eff_start > now {
+        return Err(NotYetEffective {
+            cycle: current_cycle.to_string(),
+            effective: eff_start,
+        }
+        .into());
+    }
+
+    // Streamed, so only one state is held deserialized at a time alongside the maps being built
+    let mut error = None;
+    let hashmaps = build_hashmaps(
+        config,
+        current_cycle,
+        states.map_while(|state| state.map_err(|e| error = Some(e)).ok()),
+    );
+    if let Some(e) = error {
+        return Err(e.into());
+    }
+    histogram!("chartsapi_load_duration_seconds", "cycle" => current_cycle.to_string(), "stage" => "parse")
+        .record(started.elapsed());
+    if hashmaps.counts.total < config.min_charts {
+        return Err(TooFewCharts {
+            cycle: current_cycle.to_string(),
+            total: hashmaps.counts.total,
+            minimum: config.min_charts,
+        }
+        .into());
+    }
+    #[allow(clippy::cast_precision_loss)]
+    gauge!("chartsapi_loaded_charts", "cycle" => current_cycle.to_string())
+        .set(hashmaps.counts.total as f64);
+
+    Ok(AppState {
+        cycle: current_cycle.to_string(),
+        from_effective_date: eff_start,
+        to_effective_date: eff_end,
+        loaded_at: now,
+        metafile_hash: metafile_hash(&metafile.xml),
+        validators: MetafileValidators::default(),
+        hashmaps: Arc::new(hashmaps),
+        edition: None,
+        embedded: false,
+        metafile: config.admin_token.is_some().then(|| metafile.bytes.clone()),
+        prewarmed: Arc::default(),
+    })
+}
+
+/// A metafile published ahead of its effective date, which is expected shortly before each
+/// cycle change rather than a failure
+#[derive(Debug)]
+struct NotYetEffective {
+    cycle: String,
+    effective: DateTime<Utc>,
+}
+
+impl std::fmt::Display for NotYetEffective {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cycle {} is not effective until {}",
+            self.cycle, self.effective
+        )
+    }
+}
+
+impl std::error::Error for NotYetEffective {}
+
+/// A metafile whose effective start date is not in the expected `%H%MZ %m/%d/%y` format
+#[derive(Debug)]
+struct UnparseableEffectiveDate {
+    cycle: String,
+    value: String,
+}
+
+impl std::fmt::Display for UnparseableEffectiveDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cycle {} has an unparseable effective start date {:?}",
+            self.cycle, self.value
+        )
+    }
+}
+
+impl std::error::Error for UnparseableEffectiveDate {}
+
+/// A metafile that parsed but holds fewer charts than `CHARTSAPI_MIN_CHARTS`, such as one with
+/// empty states, cities or airports, which must never replace good data
+#[derive(Debug)]
+struct TooFewCharts {
+    cycle: String,
+    total: usize,
+    minimum: usize,
+}
+
+impl std::fmt::Display for TooFewCharts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cycle {} has {} charts, fewer than the minimum of {}",
+            self.cycle, self.total, self.minimum
+        )
+    }
+}
+
+impl std::error::Error for TooFewCharts {}
+
+fn parse_effective_date(effective_date: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    NaiveDateTime::parse_from_str(effective_date, "%H%MZ %m/%d/%y").map(|d| d.and_utc())
+}
+
+async fn refresh_charts(
+    client: Client,
+    shared_config: SharedConfig,
+    app_state: Arc<RwLock<AppState>>,
+) {
+    // Jittered once at startup and on every tick, so instances started together, e.g. by a
+    // rollout, don't hit the FAA in lockstep
+    let startup_delay = jitter(shared_config.get().refresh_jitter);
+    info!(
+        "Charts refresh schedule starts in {}s",
+        startup_delay.as_secs()
+    );
+    tokio::time::sleep(startup_delay).await;
+    loop {
+        let config = shared_config.get();
+        let interval = config.refresh_interval + jitter(config.refresh_jitter);
+        info!("Next charts refresh in {}s", interval.as_secs());
+        tokio::time::sleep(interval).await;
+        // Picks up settings reloaded while sleeping
+        let config = shared_config.get();
+        match fetch_current_cycle(&client, &config.faa_info_url).await {
+            Ok((fetched_cycle, edition)) => {
+                // Always compare the metafile itself, as the FAA republishes it mid-cycle
+                // for change notices without changing the cycle. It is only downloaded again
+                // when the FAA reports it changed since the loaded copy.
+                let validators = {
+                    let reader = app_state.read().unwrap_or_else(PoisonError::into_inner);
+                    if reader.cycle == fetched_cycle {
+                        reader.validators.clone()
+                    } else {
+                        MetafileValidators::default()
+                    }
+                };
+                let metafile =
+                    match fetch_metafile_if_modified(&client, &config, &fetched_cycle, &validators)
+                        .await
+                    {
+                        Ok(Some(metafile)) => metafile,
+                        Ok(None) => {
+                            debug!("No new cycle or metafile changes found");
+                            continue;
+                        }
+                        Err(e) => {
+                            warn!("Error while fetching charts: {}", e);
+                            continue;
+                        }
+                    };
+                let current_cycle = {
+                    let mut writer = app_state.write().unwrap_or_else(PoisonError::into_inner);
+                    if metafile_hash(&metafile.xml) == writer.metafile_hash {
+                        debug!("No new cycle or metafile changes found");
+                        // Keep validators current so the next refresh can skip the download
+                        writer.validators = metafile.validators.clone();
+                        continue;
+                    }
+                    writer.cycle.clone()
+                };
+
+                if fetched_cycle.eq_ignore_ascii_case(&current_cycle) {
+                    info!("Found mid-cycle metafile update for cycle: {fetched_cycle}");
+                } else {
+                    info!("Found new cycle: {fetched_cycle}");
+                }
+                if replace_charts(&app_state, &config, &fetched_cycle, &metafile, edition) {
+                    tokio::spawn(prewarm_responses(
+                        Arc::clone(&app_state),
+                        config.hot_airports.clone(),
+                    ));
+                }
+            }
+            Err(e) => warn!("Error while fetching current cycle: {}", e),
+        }
+    }
+}
+
+/// Swaps in the charts of a refreshed metafile. One that can't replace the loaded cycle, yet or at
+/// all, leaves it served as before. Returns whether the charts were replaced.
+fn replace_charts(
+    app_state: &RwLock<AppState>,
+    config: &Config,
+    fetched_cycle: &str,
+    metafile: &Metafile,
+    edition: EditionDto,
+) -> bool {
+    let current_cycle = app_state
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .cycle
+        .clone();
+    match parse_charts(config, fetched_cycle, metafile) {
+        Ok(new_state) => {
+            *app_state.write().unwrap_or_else(PoisonError::into_inner) = AppState {
+                edition: Some(edition),
+                validators: metafile.validators.clone(),
+                ..new_state
+            };
+            return true;
+        }
+        // Not ready yet, the next refresh retries once the cycle takes effect
+        Err(e) if e.is::<NotYetEffective>() => {
+            info!("{e}, keeping cycle {current_cycle} and retrying later");
+            counter!("chartsapi_cycle_not_yet_effective_total", "cycle" => fetched_cycle.to_string())
+                .increment(1);
+        }
+        Err(e) if e.is::<TooFewCharts>() || e.is::<UnparseableEffectiveDate>() => {
+            warn!("{e}, keeping cycle {current_cycle}");
+        }
+        Err(e) => warn!("Error while fetching charts: {}", e),
+    }
+    false
+}
+
+type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// A filter logging everything at `level` or above, as configured by `CHARTSAPI_LOG_LEVEL`
+fn level_filter(level: LevelFilter) -> EnvFilter {
+    EnvFilter::default().add_directive(level.into())
+}
+
+/// Re-reads the config on SIGHUP and applies the settings that can change while running. The log
+/// filter is reset to the configured level, replacing any set through the admin endpoint.
+#[cfg(unix)]
+async fn reload_on_sighup(
+    shared_config: SharedConfig,
+    log_filter: LogFilterHandle,
+    app_state: Arc<RwLock<AppState>>,
+) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            warn!("Could not listen for SIGHUP, config reloads are disabled: {e}");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        info!("Received SIGHUP, reloading config");
+        let previous = shared_config.get();
+        let config = shared_config.reload(Config::load());
+        if let Err(e) = log_filter.reload(level_filter(config.log_level)) {
+            warn!("Could not reload log level: {e}");
+        }
+        // The loaded cycle stays until the metafile changes, so its responses are pre-warmed anew
+        if config.hot_airports != previous.hot_airports {
+            tokio::spawn(prewarm_responses(
+                Arc::clone(&app_state),
+                config.hot_airports.clone(),
+            ));
+        }
+    }
+}
+
+/// Random duration below `max`
+fn jitter(max: Duration) -> Duration {
+    let max_millis = u64::try_from(max.as_millis()).unwrap_or(u64::MAX);
+    if max_millis == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(RandomState::new().build_hasher().finish() % max_millis)
+}
+
+async fn check_integrity(client: &Client, config: &Config, app_state: &Arc<RwLock<AppState>>) {
+    let (cycle, live_hash, live_total) = {
+        let reader = app_state.read().unwrap_or_else(PoisonError::into_inner);
+        (
+            reader.cycle.clone(),
+            reader.metafile_hash,
+            reader.hashmaps.counts.total,
+        )
+    };
+    let fetched = match load_charts(client, config, &cycle).await {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            warn!("Error while running integrity check: {}", e);
+            return;
+        }
+    };
+    counter!("chartsapi_integrity_checks_total").increment(1);
+
+    // A changed metafile is a legitimate update that the refresh loop will pick up
+    if fetched.metafile_hash != live_hash {
+        debug!("Skipping integrity comparison, metafile changed since last load");
+    } else if fetched.hashmaps.counts.total == live_total {
+        debug!("Integrity check passed for cycle {cycle} with {live_total} charts");
+    } else {
+        warn!(
+            "Integrity check found drift for cycle {cycle}: {live_total} charts live, {} charts fetched",
+            fetched.hashmaps.counts.total
+        );
+        counter!("chartsapi_integrity_drift_total").increment(1);
+    }
+}
+
+/// Fetches the current cycle along with the FAA's edition details it was derived from
+async fn fetch_current_cycle(
+    client: &Client,
+    info_url: &str,
+) -> Result<(String, EditionDto), anyhow::Error> {
+    info!("Fetching current cycle");
+    // Prefers JSON but accepts the XML the endpoint has always served, whichever it answers with
+    let response = faa_send(
+        client
+            .get(info_url)
+            .header(header::ACCEPT, "application/json, application/xml;q=0.9"),
+    )
+    .await?;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("json"));
+    let body = faa_metafile::decode_document(&response.bytes().await.map_err(UpstreamError::from)?);
+    let edition = if is_json {
+        serde_json::from_str::<JsonProductSet>(&body)?
+            .into_edition()
+            .ok_or_else(|| anyhow::anyhow!("No edition in the FAA info response"))?
+    } else {
+        from_str::<ProductSet>(&body)?.edition
+    };
+    let date = NaiveDate::parse_from_str(&edition.date, "%m/%d/%Y")?;
+    let cycle_str = format!("{}{}", date.format("%y"), edition.number);
+    info!("Found current cycle: {cycle_str}");
+    Ok((
+        cycle_str,
+        EditionDto {
+            name: edition.name,
+            date: edition.date,
+            number: edition.number,
+            geoname: edition.geoname,
+            format: edition.format,
+            products: edition
+                .products
+                .into_iter()
+                .map(|product| ProductDto {
+                    name: product.name,
+                    url: product.url,
+                })
+                .collect(),
+        },
+    ))
+}
+
+static FAA_BREAKER: CircuitBreaker =
+    CircuitBreaker::new("faa", FAA_FAILURE_THRESHOLD, FAA_COOLDOWN);
+
+/// Returned instead of calling the FAA while its circuit breaker is open
+#[derive(Debug)]
+struct CircuitOpen;
+
+impl std::fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FAA requests are paused after repeated failures")
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+/// A failed FAA request, with the status the FAA answered with if it got that far
+#[derive(Debug)]
+struct UpstreamError {
+    status: Option<u16>,
+    source: reqwest::Error,
+}
+
+impl From<reqwest::Error> for UpstreamError {
+    fn from(source: reqwest::Error) -> Self {
+        Self {
+            status: source.status().map(|status| status.as_u16()),
+            source,
+        }
+    }
+}
+
+impl std::fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FAA request failed: {}", self.source)
+    }
+}
+
+impl std::error::Error for UpstreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// The status the FAA answered a failed request with, if any
+fn upstream_status(e: &anyhow::Error) -> Option<u16> {
+    e.chain()
+        .find_map(|e| e.downcast_ref::<UpstreamError>())
+        .and_then(|e| e.status)
+}
+
+/// Answers a failure caused by the FAA, so clients can tell it from our own errors and retry: a
+/// 503 while FAA requests are paused, or else a 502 with the FAA's status when it answered
+fn faa_error_response(e: &anyhow::Error, message: &'static str) -> Response {
+    if e.chain().any(<dyn std::error::Error>::is::<CircuitOpen>) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorMessage {
+                status: "error",
+                status_code: "503",
+                message: "FAA requests are temporarily paused, please retry later.",
+            }),
+        )
+            .into_response();
+    }
+    (
+        StatusCode::BAD_GATEWAY,
+        Json(UpstreamErrorDto {
+            error: ErrorMessage {
+                status: "error",
+                status_code: "502",
+                message,
+            },
+            upstream_status: upstream_status(e),
+        }),
+    )
+        .into_response()
+}
+
+/// GETs from the FAA through its circuit breaker. Only transport errors and server errors count
+/// as failures, so a missing chart doesn't count against the FAA.
+async fn faa_get(client: &Client, url: &str) -> Result<reqwest::Response, anyhow::Error> {
+    faa_send(client.get(url)).await
+}
+
+async fn faa_send(request: reqwest::RequestBuilder) -> Result<reqwest::Response, anyhow::Error> {
+    if !FAA_BREAKER.allow() {
+        return Err(CircuitOpen.into());
+    }
+    let response = request.send().await;
+    match &response {
+        Ok(response) if !response.status().is_server_error() => FAA_BREAKER.record_success(),
+        _ => FAA_BREAKER.record_failure(),
+    }
+    Ok(response
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(UpstreamError::from)?)
+}
+
+fn build_client(timeout: Duration) -> reqwest::Result<Client> {
+    Client::builder()
+        // Sends `Accept-Encoding: gzip` and transparently decompresses, notably for the metafile
+        .gzip(true)
+        // Without a timeout, a stalled FAA response would hang a load or refresh indefinitely
+        .timeout(timeout)
+        .redirect(Policy::custom(|attempt| {
+            if attempt.previous().len() > MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else {
+                info!("Following redirect to {}", attempt.url());
+                attempt.follow()
+            }
+        }))
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+    use axum::http::HeaderName;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt as _;
+
+    const METAFILE: &[u8] = include_bytes!("../tests/fixtures/d-tpp_Metafile.xml");
+
+    /// Config read from `vars` alone. The fixture cycle has long expired, so unless overridden the
+    /// grace period is long enough to keep serving it.
+    fn test_config(vars: &[(&str, &str)]) -> Config {
+        Config::from_vars(|key| {
+            vars.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| (*value).to_string())
+                .or_else(|| (key == "CHARTSAPI_EXPIRY_GRACE_SECS").then(|| u32::MAX.to_string()))
+        })
+    }
+
+    fn test_state(config: Config, metafile: &[u8]) -> ServerState {
+        let metafile = Metafile::new(
+            Bytes::copy_from_slice(metafile),
+            MetafileValidators::default(),
+        );
+        let cycle = StateReader::new(&metafile.xml).unwrap().1.cycle;
+        let app_state = parse_charts(&config, &cycle, &metafile).unwrap();
+        ServerState {
+            app_state: Arc::new(RwLock::new(app_state)),
+            client: build_client(config.fetch_timeout).unwrap(),
+            cache: Arc::new(InMemoryChartCache::new(CYCLE_CACHE_CAPACITY)),
+            enricher: None,
+            aliases: Arc::new(ChartAliases::default()),
+            log_filter: reload::Layer::new(EnvFilter::default()).1,
+            config: SharedConfig::new(config),
+        }
+    }
+
+    fn test_router(vars: &[(&str, &str)]) -> Router {
+        router_for(test_state(test_config(vars), METAFILE))
+    }
+
+    fn router_for(state: ServerState) -> Router {
+        build_router(
+            state,
+            PathBuf::from("assets"),
+            PrometheusBuilder::new().build_recorder().handle(),
+        )
+    }
+
+    async fn send(router: Router, request: Request) -> (StatusCode, HeaderMap, Bytes) {
+        let response = router.oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        (
+            parts.status,
+            parts.headers,
+            to_bytes(body, usize::MAX).await.unwrap(),
+        )
+    }
+
+    /// GETs `uri` from the fixture router and parses the JSON answer
+    async fn get_json(uri: &str) -> (StatusCode, serde_json::Value) {
+        let (status, headers, body) = send(
+            test_router(&[]),
+            Request::get(uri).body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(headers[header::CONTENT_TYPE], "application/json", "{uri}");
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    /// Serves `router` on a local port in place of the FAA, returning the base URL to configure
+    async fn mock_faa(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    /// A mock FAA answering every metafile request with `response`
+    async fn mock_metafile(response: impl IntoResponse + Clone + Send + Sync + 'static) -> String {
+        mock_faa(Router::new().route(
+            "/:cycle/xml_data/d-tpp_Metafile.xml",
+            get(move || std::future::ready(response.clone())),
+        ))
+        .await
+    }
+
+    /// A mock PDF host redirecting every chart `hops` times before serving `%PDF`
+    async fn redirecting_pdf_host(hops: usize) -> String {
+        mock_faa(
+            Router::new()
+                .route(
+                    "/:cycle/:pdf",
+                    get(move || std::future::ready(Redirect::temporary(&format!("/hops/{hops}")))),
+                )
+                .route(
+                    "/hops/:remaining",
+                    get(|Path(remaining): Path<usize>| async move {
+                        if remaining > 1 {
+                            Redirect::temporary(&format!("/hops/{}", remaining - 1)).into_response()
+                        } else {
+                            "%PDF".into_response()
+                        }
+                    }),
+                ),
+        )
+        .await
+    }
+
+    fn prefix_headers(prefix: &str) -> HeaderMap {
+        HeaderMap::from_iter([(
+            HeaderName::from_static("x-forwarded-prefix"),
+            HeaderValue::from_str(prefix).unwrap(),
+        )])
+    }
+
+    #[test]
+    fn forwarded_prefix_accepts_absolute_paths() {
+        assert_eq!(
+            forwarded_prefix(&prefix_headers("/chartsapi/")),
+            "/chartsapi"
+        );
+        assert_eq!(forwarded_prefix(&prefix_headers("/a/b")), "/a/b");
+        assert_eq!(forwarded_prefix(&HeaderMap::new()), "");
+    }
+
+    #[test]
+    fn forwarded_prefix_rejects_other_hosts() {
+        for prefix in [
+            "//evil.com",
+            "///evil.com",
+            "https://evil.com",
+            "/\\evil.com",
+            "/x?y",
+            "javascript:alert(1)",
+            "chartsapi",
+        ] {
+            assert_eq!(forwarded_prefix(&prefix_headers(prefix)), "", "{prefix}");
+        }
+    }
+
+    #[test]
+    fn links_base_ignores_untrusted_forwarded_prefix() {
+        let headers = prefix_headers("/chartsapi");
+        assert_eq!(links_base(&test_config(&[]), &headers), "");
+        let trusted = test_config(&[("CHARTSAPI_TRUST_FORWARDED_PREFIX", "true")]);
+        assert_eq!(links_base(&trusted, &headers), "/chartsapi");
+        let based = test_config(&[
+            ("CHARTSAPI_TRUST_FORWARDED_PREFIX", "true"),
+            ("CHARTSAPI_BASE_PATH", "/base"),
+        ]);
+        assert_eq!(links_base(&based, &headers), "/base");
+    }
+
+    #[tokio::test]
+    async fn links_use_trusted_forwarded_prefix_and_vary_on_it() {
+        for (trust, base, vary) in [
+            ("false", "", "Accept, Accept-Encoding"),
+            (
+                "true",
+                "/chartsapi",
+                "Accept, Accept-Encoding, X-Forwarded-Prefix",
+            ),
+        ] {
+            let router = test_router(&[("CHARTSAPI_TRUST_FORWARDED_PREFIX", trust)]);
+            let request = Request::get("/v1/charts?apt=JFK&hateoas=true")
+                .header("x-forwarded-prefix", "/chartsapi")
+                .body(Body::empty())
+                .unwrap();
+            let (status, headers, body) = send(router, request).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers[header::VARY], vary);
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                body["JFK"][0]["_links"]["charts"],
+                format!("{base}/v1/charts/JFK")
+            );
+        }
+    }
+
+    async fn past_cycle_status(faa_base_url: &str, cycle: &str) -> (StatusCode, serde_json::Value) {
+        let router = test_router(&[("CHARTSAPI_FAA_BASE_URL", faa_base_url)]);
+        let (status, _, body) = send(
+            router,
+            Request::get(format!("/v1/charts?apt=JFK&cycle={cycle}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn past_cycle_missing_at_the_faa_is_not_found() {
+        let faa = mock_metafile(StatusCode::NOT_FOUND).await;
+        let (status, body) = past_cycle_status(&faa, "2401").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["status_code"], "404");
+    }
+
+    #[tokio::test]
+    async fn past_cycle_faa_failure_is_a_bad_gateway() {
+        let faa = mock_metafile(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let (status, body) = past_cycle_status(&faa, "2402").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body["status_code"], "502");
+        assert_eq!(body["upstream_status"], 500);
+    }
+
+    #[tokio::test]
+    async fn past_cycle_not_yet_effective_is_a_conflict() {
+        let metafile = String::from_utf8_lossy(METAFILE).replace(
+            r#"from_edate="0901Z  10/03/24" to_edate="0901Z  10/31/24""#,
+            r#"from_edate="0901Z  10/03/67" to_edate="0901Z  10/31/67""#,
+        );
+        let faa = mock_metafile(metafile).await;
+        let (status, body) = past_cycle_status(&faa, "2403").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["status_code"], "409");
+    }
+
+    #[tokio::test]
+    async fn past_cycle_unreadable_metafile_is_an_internal_error() {
+        for (cycle, metafile) in [
+            ("2404", "not a metafile"),
+            (
+                "2405",
+                r#"<digital_tpp cycle="2405" from_edate="soon" to_edate=""></digital_tpp>"#,
+            ),
+        ] {
+            let faa = mock_metafile(metafile).await;
+            let (status, body) = past_cycle_status(&faa, cycle).await;
+            assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{metafile}");
+            assert_eq!(body["status_code"], "500");
+        }
+    }
+
+    #[tokio::test]
+    async fn past_cycle_loads_from_the_faa() {
+        let faa = mock_metafile(METAFILE).await;
+        let (status, body) = past_cycle_status(&faa, "2406").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["JFK"][0]["pdf_path"]
+            .as_str()
+            .unwrap()
+            .starts_with(&format!("{faa}/2406/")));
+    }
+
+    #[test]
+    fn amended_since_counts_back_whole_cycles() {
+        let today = NaiveDate::from_ymd_opt(2024, 10, 31).unwrap();
+        assert_eq!(amended_since(today, 0), today);
+        assert_eq!(
+            amended_since(today, 1),
+            NaiveDate::from_ymd_opt(2024, 10, 3).unwrap()
+        );
+    }
+
+    #[test]
+    fn amended_since_clamps_windows_past_the_earliest_date() {
+        let today = NaiveDate::from_ymd_opt(2024, 10, 31).unwrap();
+        assert_eq!(amended_since(today, 100_000_000), NaiveDate::MIN);
+        assert_eq!(amended_since(today, u32::MAX), NaiveDate::MIN);
+        let cycles_to_min =
+            u32::try_from((today - NaiveDate::MIN).num_days() / CYCLE_DAYS).unwrap();
+        assert!(amended_since(today, cycles_to_min) > NaiveDate::MIN);
+        assert_eq!(amended_since(today, cycles_to_min + 1), NaiveDate::MIN);
+    }
+
+    #[tokio::test]
+    async fn huge_amended_within_keeps_every_dated_chart() {
+        for cycles in ["100000000", "4294967295"] {
+            let (status, _, body) = send(
+                test_router(&[]),
+                Request::get(format!("/v1/charts?apt=JFK&amended_within={cycles}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert!(body["JFK"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .all(|chart| !chart["amendment_date"].as_str().unwrap().is_empty()));
+            assert!(!body["JFK"].as_array().unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn only_successful_responses_are_publicly_cached() {
+        for (uri, status, cache_control) in [
+            ("/v1/charts?apt=JFK", StatusCode::OK, "public, max-age=3600"),
+            ("/v1/charts", StatusCode::NOT_FOUND, "no-store"),
+            (
+                "/v1/charts?apt=JFK&group=9",
+                StatusCode::FORBIDDEN,
+                "no-store",
+            ),
+            ("/v1/charts/JFK", StatusCode::OK, "public, max-age=3600"),
+            ("/v1/charts/ZZZ", StatusCode::NOT_FOUND, "no-store"),
+            ("/v1/cycle", StatusCode::OK, "public, max-age=300"),
+        ] {
+            let (actual, headers, _) = send(
+                test_router(&[]),
+                Request::get(uri).body(Body::empty()).unwrap(),
+            )
+            .await;
+            assert_eq!(actual, status, "{uri}");
+            assert_eq!(headers[header::CACHE_CONTROL], cache_control, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn metafile_is_served_exactly_as_downloaded() {
+        let bom = [b"\xEF\xBB\xBF", METAFILE].concat();
+        let latin1 = String::from_utf8_lossy(METAFILE)
+            .replace(r#"encoding="UTF-8""#, r#"encoding="ISO-8859-1""#)
+            .replace("LUIS MUNOZ MARIN", "LUIS MUÑOZ MARÍN");
+        let (latin1, _, _) = encoding_rs::WINDOWS_1252.encode(&latin1);
+        for metafile in [METAFILE, &bom, &latin1] {
+            let config = test_config(&[("CHARTSAPI_ADMIN_TOKEN", "secret")]);
+            let router = router_for(test_state(config, metafile));
+            let (status, _, body) = send(
+                router,
+                Request::get("/v1/metafile.xml")
+                    .header(header::AUTHORIZATION, "Bearer secret")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body, metafile);
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_query_parameters_are_json_errors() {
+        for uri in [
+            "/v1/charts?apt=JFK&pretty=abc",
+            "/v1/charts?apt=JFK&flatten=abc",
+            "/v1/groups?pretty=abc",
+            "/v1/charts/JFK?pretty=abc",
+            "/v1/charts/JFK/latest-amendment?pretty=abc",
+            "/v1/charts/JFK/pdf-urls?with_names=abc",
+            "/v1/charts.csv?state=NY&state=NJ",
+            "/v1/charts/JFK/exact",
+            "/v1/charts/JFK/search",
+            "/v1/charts/JFK/search?term=ALTERNATE&pdf=abc",
+            "/v1/charts/JFK/ALTERNATE?suggest=abc",
+        ] {
+            let (status, body) = get_json(uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+            assert_eq!(body["status_code"], "400", "{uri}");
+        }
+        let (status, headers, _) = send(
+            test_router(&[]),
+            Request::head("/v1/charts?apt=JFK&pretty=abc")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+
+        let (status, _, body) = send(
+            test_router(&[]),
+            Request::post("/v1/idents/resolve")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"idents": "JFK"}"#))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status_code"], "400");
+    }
+
+    #[tokio::test]
+    async fn non_integer_group_codes_are_invalid_group_codes() {
+        for uri in [
+            "/v1/charts?apt=JFK&group=abc",
+            "/v1/charts/JFK/pdf-urls?group=abc",
+            "/v1/charts.csv?group=abc",
+        ] {
+            let (status, body) = get_json(uri).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{uri}");
+            assert_eq!(body["status_code"], "403", "{uri}");
+        }
+    }
+
+    #[test]
+    fn jitter_stays_below_its_maximum() {
+        assert_eq!(jitter(Duration::ZERO), Duration::ZERO);
+        assert_eq!(jitter(Duration::from_nanos(1)), Duration::ZERO);
+        let max = Duration::from_mins(5);
+        for _ in 0..1000 {
+            assert!(jitter(max) < max);
+        }
+    }
+
+    #[tokio::test]
+    async fn chart_pdfs_follow_redirects_up_to_the_limit() {
+        for hops in [1, MAX_REDIRECTS] {
+            let pdf_host = redirecting_pdf_host(hops).await;
+            let router = test_router(&[("CHARTSAPI_PDF_BASE_URL", &pdf_host)]);
+            let (status, _, body) = send(
+                router,
+                Request::get("/v1/charts/JFK/ALTERNATE/pdf")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK, "{hops} hops");
+            assert_eq!(body, "%PDF", "{hops} hops");
+        }
+
+        let pdf_host = redirecting_pdf_host(MAX_REDIRECTS + 1).await;
+        let router = test_router(&[("CHARTSAPI_PDF_BASE_URL", &pdf_host)]);
+        let (status, _, _) = send(
+            router,
+            Request::get("/v1/charts/JFK/ALTERNATE/pdf")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn metafile_is_requested_gzipped_and_decompressed() {
+        let faa = mock_faa(Router::new().route(
+            "/:cycle/xml_data/d-tpp_Metafile.xml",
+            get(|headers: HeaderMap| async move {
+                let accepts_gzip = headers
+                    .get(header::ACCEPT_ENCODING)
+                    .and_then(|value| value.to_str().ok())
+                    .is_some_and(|value| value.contains("gzip"));
+                if !accepts_gzip {
+                    return StatusCode::NOT_ACCEPTABLE.into_response();
+                }
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                std::io::Write::write_all(&mut encoder, METAFILE).unwrap();
+                (
+                    [(header::CONTENT_ENCODING, "gzip")],
+                    encoder.finish().unwrap(),
+                )
+                    .into_response()
+            }),
+        ))
+        .await;
+        let config = test_config(&[("CHARTSAPI_FAA_BASE_URL", &faa)]);
+        let client = build_client(config.fetch_timeout).unwrap();
+        let metafile = fetch_metafile(&client, &config, "2407").await.unwrap();
+        assert_eq!(metafile.bytes, METAFILE);
+    }
+
+    #[test]
+    fn strip_pdf_extension_only_strips_a_trailing_pdf() {
+        assert_eq!(strip_pdf_extension("ALTERNATE.pdf"), ("ALTERNATE", true));
+        assert_eq!(strip_pdf_extension("ALTERNATE.PDF"), ("ALTERNATE", true));
+        assert_eq!(strip_pdf_extension("ALTERNATE"), ("ALTERNATE", false));
+        assert_eq!(strip_pdf_extension(".pdf"), ("", true));
+        assert_eq!(strip_pdf_extension("pdf"), ("pdf", false));
+        assert_eq!(strip_pdf_extension("ILS.pdf RWY"), ("ILS.pdf RWY", false));
+    }
+
+    #[tokio::test]
+    async fn pdf_extension_streams_the_chart_the_search_redirects_to() {
+        // Answers with the name of the PDF requested, to tell which chart was streamed
+        let pdf_host = mock_faa(Router::new().route(
+            "/:cycle/:pdf",
+            get(|Path((_, pdf)): Path<(String, String)>| async move { pdf }),
+        ))
+        .await;
+        let router = test_router(&[("CHARTSAPI_PDF_BASE_URL", &pdf_host)]);
+        let (status, headers, _) = send(
+            router.clone(),
+            Request::get("/v1/charts/JFK/ALTERNATE")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(
+            headers[header::LOCATION],
+            format!("{pdf_host}/2410/NE2ALT.PDF")
+        );
+
+        for uri in [
+            "/v1/charts/JFK/ALTERNATE.pdf",
+            "/v1/charts/JFK/ALTERNATE.PDF",
+        ] {
+            let (status, headers, body) = send(
+                router.clone(),
+                Request::get(uri).body(Body::empty()).unwrap(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            assert_eq!(headers[header::CONTENT_TYPE], "application/pdf", "{uri}");
+            assert_eq!(body, "NE2ALT.PDF", "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn mirrored_pdfs_are_served_before_the_faa() {
+        let pdf_dir = std::env::temp_dir().join(format!("chartsapi-mirror-{}", std::process::id()));
+        std::fs::create_dir_all(pdf_dir.join("2410")).unwrap();
+        std::fs::write(pdf_dir.join("2410").join("NE2ALT.PDF"), "mirrored").unwrap();
+        let pdf_host = mock_faa(Router::new().route(
+            "/:cycle/:pdf",
+            get(|Path((_, pdf)): Path<(String, String)>| async move { pdf }),
+        ))
+        .await;
+        let router = test_router(&[
+            ("CHARTSAPI_PDF_DIR", pdf_dir.to_str().unwrap()),
+            ("CHARTSAPI_PDF_BASE_URL", &pdf_host),
+        ]);
+
+        for (uri, status, body) in [
+            ("/v1/charts/JFK/ALTERNATE", StatusCode::OK, "mirrored"),
+            ("/v1/charts/JFK/ALTERNATE/pdf", StatusCode::OK, "mirrored"),
+            ("/v1/charts/JFK/TAKEOFF", StatusCode::TEMPORARY_REDIRECT, ""),
+            ("/v1/charts/JFK/TAKEOFF/pdf", StatusCode::OK, "NE2TO.PDF"),
+        ] {
+            let (actual, _, actual_body) = send(
+                router.clone(),
+                Request::get(uri).body(Body::empty()).unwrap(),
+            )
+            .await;
+            assert_eq!(actual, status, "{uri}");
+            assert_eq!(actual_body, body, "{uri}");
+        }
+        std::fs::remove_dir_all(pdf_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_metafile_fetches_share_one_download() {
+        let downloads = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&downloads);
+        let faa = mock_faa(Router::new().route(
+            "/:cycle/xml_data/d-tpp_Metafile.xml",
+            get(move || async move {
+                counted.fetch_add(1, Ordering::SeqCst);
+                // Keeps the first download in flight while the second fetch starts
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                METAFILE
+            }),
+        ))
+        .await;
+        let config = test_config(&[("CHARTSAPI_FAA_BASE_URL", &faa)]);
+        let client = build_client(config.fetch_timeout).unwrap();
+
+        let (first, second) = tokio::join!(
+            fetch_metafile(&client, &config, "2408"),
+            fetch_metafile(&client, &config, "2408"),
+        );
+        assert!(Arc::ptr_eq(&first.unwrap(), &second.unwrap()));
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+
+        // Only concurrent fetches are shared, later ones download again
+        fetch_metafile(&client, &config, "2408").await.unwrap();
+        assert_eq!(downloads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn grouped_charts_keep_the_flat_order() {
+        for order in ["", "&order=seq"] {
+            let (_, flat) = get_json(&format!("/v1/charts?apt=JFK{order}")).await;
+            let (_, grouped) = get_json(&format!("/v1/charts?apt=JFK&group=1{order}")).await;
+            let names = |charts: &serde_json::Value| -> Vec<String> {
+                charts
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|chart| format!("{} {}", chart["chart_code"], chart["chart_name"]))
+                    .collect()
+            };
+            let flat = names(&flat["JFK"]);
+            for (group, charts) in grouped["JFK"].as_object().unwrap() {
+                let charts = names(charts);
+                let in_flat_order: Vec<_> = flat
+                    .iter()
+                    .filter(|name| charts.contains(name))
+                    .cloned()
+                    .collect();
+                assert_eq!(charts, in_flat_order, "{group}{order}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn unchanged_metafile_is_not_downloaded_again() {
+        let faa = mock_faa(Router::new().route(
+            "/:cycle/xml_data/d-tpp_Metafile.xml",
+            get(|headers: HeaderMap| async move {
+                let if_none_match = headers.get(header::IF_NONE_MATCH);
+                if if_none_match.is_some_and(|etag| etag == "\"v1\"") {
+                    return StatusCode::NOT_MODIFIED.into_response();
+                }
+                (
+                    [
+                        (header::ETAG, "\"v1\""),
+                        (header::LAST_MODIFIED, "Thu, 03 Oct 2024 09:01:00 GMT"),
+                    ],
+                    METAFILE,
+                )
+                    .into_response()
+            }),
+        ))
+        .await;
+        let config = test_config(&[("CHARTSAPI_FAA_BASE_URL", &faa)]);
+        let client = build_client(config.fetch_timeout).unwrap();
+
+        let metafile = fetch_metafile(&client, &config, "2409").await.unwrap();
+        assert_eq!(metafile.validators.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(
+            metafile.validators.last_modified.as_deref(),
+            Some("Thu, 03 Oct 2024 09:01:00 GMT")
+        );
+        let unchanged =
+            fetch_metafile_if_modified(&client, &config, "2409", &metafile.validators).await;
+        assert!(unchanged.unwrap().is_none());
+
+        let stale = MetafileValidators {
+            etag: Some("\"v0\"".to_string()),
+            last_modified: None,
+        };
+        let changed = fetch_metafile_if_modified(&client, &config, "2409", &stale).await;
+        assert_eq!(changed.unwrap().unwrap().bytes, METAFILE);
+    }
+
+    fn with_effective_dates(from_edate: &str, to_edate: &str) -> Metafile {
+        let metafile = String::from_utf8_lossy(METAFILE).replace(
+            r#"from_edate="0901Z  10/03/24" to_edate="0901Z  10/31/24""#,
+            &format!(r#"from_edate="{from_edate}" to_edate="{to_edate}""#),
+        );
+        Metafile::new(Bytes::from(metafile), MetafileValidators::default())
+    }
+
+    #[test]
+    fn unparseable_effective_start_date_is_rejected() {
+        for from_edate in ["", "soon", "10/03/24", "0901Z 13/03/24"] {
+            let metafile = with_effective_dates(from_edate, "0901Z  10/31/24");
+            let Err(e) = parse_charts(&test_config(&[]), "2410", &metafile) else {
+                panic!("{from_edate:?} was accepted");
+            };
+            assert!(e.is::<UnparseableEffectiveDate>(), "{from_edate:?}: {e}");
+        }
+    }
+
+    #[test]
+    fn unparseable_effective_end_date_defaults_to_one_cycle() {
+        let metafile = with_effective_dates("0901Z  10/03/24", "later");
+        let app_state = parse_charts(&test_config(&[]), "2410", &metafile).unwrap();
+        assert_eq!(
+            app_state.from_effective_date,
+            parse_effective_date("0901Z  10/03/24").unwrap()
+        );
+        assert_eq!(
+            app_state.to_effective_date,
+            app_state.from_effective_date + TimeDelta::days(CYCLE_DAYS)
+        );
+    }
+
+    #[tokio::test]
+    async fn handler_panics_are_json_errors() {
+        async fn panic_with_str() {
+            panic!("static message");
+        }
+        async fn panic_with_string() {
+            panic!("formatted {}", "message");
+        }
+        let router = with_request_ids(
+            Router::new()
+                .route("/str", get(panic_with_str))
+                .route("/string", get(panic_with_string)),
+        );
+        for uri in ["/str", "/string"] {
+            let (status, headers, body) = send(
+                router.clone(),
+                Request::get(uri).body(Body::empty()).unwrap(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{uri}");
+            assert_eq!(headers[header::CONTENT_TYPE], "application/json", "{uri}");
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["status_code"], "500", "{uri}");
+            assert!(!headers["x-request-id"].is_empty(), "{uri}");
+        }
+
+        // A client's own ID is kept, so it can quote it when reporting the error
+        let (status, headers, _) = send(
+            router,
+            Request::get("/str")
+                .header("x-request-id", "client-id")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(headers["x-request-id"], "client-id");
+    }
+
+    #[tokio::test]
+    async fn stalled_faa_requests_time_out() {
+        let faa = mock_faa(Router::new().route(
+            "/:cycle/xml_data/d-tpp_Metafile.xml",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                METAFILE
+            }),
+        ))
+        .await;
+        let router = test_router(&[
+            ("CHARTSAPI_FAA_BASE_URL", &faa),
+            ("CHARTSAPI_FETCH_TIMEOUT_SECS", "1"),
+        ]);
+        let started = Instant::now();
+        let (status, _, body) = send(
+            router,
+            Request::get("/v1/charts?apt=JFK&cycle=2411")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status_code"], "502");
+        assert!(body["upstream_status"].is_null());
+    }
+
+    #[tokio::test]
+    async fn group_zero_puts_every_chart_in_exactly_one_bucket() {
+        for apt in ["JFK", "DFW", "SJU"] {
+            let (_, flat) = get_json(&format!("/v1/charts?apt={apt}")).await;
+            let (status, grouped) = get_json(&format!("/v1/charts?apt={apt}&group=0")).await;
+            assert_eq!(status, StatusCode::OK, "{apt}");
+            assert!(grouped[apt]["APD"].is_array(), "{apt} has a diagram");
+            let mut bucketed: Vec<String> = Vec::new();
+            for (bucket, charts) in grouped[apt].as_object().unwrap() {
+                for chart in charts.as_array().unwrap() {
+                    let is_apd = chart["chart_code"] == "APD";
+                    assert_eq!(bucket == "APD", is_apd, "{apt}: {chart} in {bucket}");
+                    bucketed.push(chart.to_string());
+                }
+            }
+            let mut flat: Vec<String> = flat[apt]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(serde_json::Value::to_string)
+                .collect();
+            bucketed.sort_unstable();
+            flat.sort_unstable();
+            assert_eq!(bucketed, flat, "{apt}");
+        }
+    }
+
+    #[tokio::test]
+    async fn searches_matching_only_deleted_charts_are_gone() {
+        let metafile = String::from_utf8_lossy(METAFILE).replace(
+            "<chart_name>VOR RWY 13L</chart_name>",
+            "<chart_name>NDB RWY 13L</chart_name>",
+        );
+        let router = router_for(test_state(test_config(&[]), metafile.as_bytes()));
+        for (uri, status) in [
+            ("/v1/charts/JFK/NDB", StatusCode::GONE),
+            ("/v1/charts/KJFK/NDB%20RWY%2013L", StatusCode::GONE),
+            (
+                "/v1/charts/JFK/VOR%20RWY%2013L",
+                StatusCode::TEMPORARY_REDIRECT,
+            ),
+            ("/v1/charts/JFK/TACAN", StatusCode::NOT_FOUND),
+            ("/v1/charts/LGA/NDB", StatusCode::NOT_FOUND),
+        ] {
+            let (actual, _, body) = send(
+                router.clone(),
+                Request::get(uri).body(Body::empty()).unwrap(),
+            )
+            .await;
+            assert_eq!(actual, status, "{uri}");
+            if status == StatusCode::GONE {
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(body["status_code"], "410", "{uri}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn charts_under_unknown_state_codes_are_still_served() {
+        let metafile = String::from_utf8_lossy(METAFILE).replace(
+            r#"<state_code ID="NY" state_fullname="New York">"#,
+            r#"<state_code ID="XX" state_fullname="Nowhere">"#,
+        );
+        let router = router_for(test_state(test_config(&[]), metafile.as_bytes()));
+        let (status, _, body) = send(
+            router,
+            Request::get("/v1/charts?apt=JFK")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["JFK"][0]["state"], "XX");
+    }
+
+    #[tokio::test]
+    async fn flattened_charts_add_up_to_the_per_airport_charts() {
+        let airports = ["SJU", "JFK", "DFW", "LGA"];
+        for group in ["", "&group=0", "&group=1", "&group=3", "&group=7"] {
+            let apt = airports.join(",");
+            let (_, per_airport) = get_json(&format!("/v1/charts?apt={apt}{group}")).await;
+            let (status, flat) =
+                get_json(&format!("/v1/charts?apt={apt}{group}&flatten=true")).await;
+            assert_eq!(status, StatusCode::OK, "{group}");
+
+            let mut expected = Vec::new();
+            for airport in airports {
+                let count = match &per_airport[airport] {
+                    serde_json::Value::Array(charts) => charts.len(),
+                    serde_json::Value::Object(groups) => groups
+                        .values()
+                        .map(|charts| charts.as_array().unwrap().len())
+                        .sum(),
+                    other => panic!("{airport}{group}: {other}"),
+                };
+                expected.extend(std::iter::repeat_n(airport, count));
+            }
+            let idents: Vec<&str> = flat
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|chart| chart["faa_ident"].as_str().unwrap())
+                .collect();
+            assert_eq!(idents, expected, "{group}");
+        }
+    }
+
+    #[tokio::test]
+    async fn results_are_keyed_by_faa_ident_by_default_and_flag_icao_fallbacks() {
+        let keys = |body: &serde_json::Value| -> Vec<String> {
+            body.as_object().unwrap().keys().cloned().collect()
+        };
+        let (status, body) = get_json("/v1/charts?apt=KJFK,T76").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(keys(&body), ["JFK", "T76"]);
+
+        let (_, body) = get_json("/v1/charts?apt=KJFK,T76&key=icao").await;
+        assert_eq!(keys(&body), ["KJFK", "T76", "without_icao"]);
+        assert_eq!(body["without_icao"], serde_json::json!(["T76"]));
+
+        let (_, body) = get_json("/v1/charts?apt=KJFK,T76&key=requested").await;
+        assert_eq!(keys(&body), ["KJFK", "T76"]);
+    }
+
+    #[tokio::test]
+    async fn cors_origins_apply_on_reload() {
+        let state = test_state(
+            test_config(&[("CHARTSAPI_CORS_ORIGINS", "https://a.example")]),
+            METAFILE,
+        );
+        // One router throughout, so the reload has to reach the layer it was built with
+        let router = router_for(state.clone());
+        let allowed_origin = |origin: &'static str| {
+            let router = router.clone();
+            async move {
+                let (status, headers, _) = send(
+                    router,
+                    Request::get("/v1/cycle")
+                        .header(header::ORIGIN, origin)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await;
+                assert_eq!(status, StatusCode::OK);
+                headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).cloned()
+            }
+        };
+        assert_eq!(
+            allowed_origin("https://a.example").await.unwrap(),
+            "https://a.example"
+        );
+        assert!(allowed_origin("https://b.example").await.is_none());
+
+        state.config.reload(test_config(&[(
+            "CHARTSAPI_CORS_ORIGINS",
+            "https://b.example",
+        )]));
+        assert!(allowed_origin("https://a.example").await.is_none());
+        assert_eq!(
+            allowed_origin("https://b.example").await.unwrap(),
+            "https://b.example"
+        );
+    }
+
+    #[tokio::test]
+    async fn book_order_sorts_jfk_as_printed() {
+        let (status, body) = get_json("/v1/charts?apt=JFK&order=book").await;
+        assert_eq!(status, StatusCode::OK);
+        let names: Vec<&str> = body["JFK"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|chart| chart["chart_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "TAKEOFF MINIMUMS",
+                "ALTERNATE MINIMUMS",
+                "HOT SPOT",
+                "LAHSO",
+                "ILS OR LOC RWY 04L",
+                "ILS OR LOC RWY 04R",
+                "ILS OR LOC RWY 13L",
+                "ILS OR LOC RWY 22L",
+                "ILS OR LOC RWY 31R",
+                "ILS RWY 22R (SA CAT I)",
+                "RNAV (GPS) RWY 04L",
+                "RNAV (RNP) Z RWY 13L",
+                "VOR RWY 13L/13R",
+                "AIRPORT DIAGRAM",
+                "KENNEDY FIVE",
+                "JFK THREE",
+                "TAKEOFF OBSTACLE NOTES",
+                "DIVERSE VECTOR AREA",
+                "CAMRN FOUR (RNAV)",
+                "IGN ONE",
+                "LENDY EIGHT",
+                "PARCH THREE (RNAV)",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn aliases_match_visual_and_circling_approaches() {
+        let metafile =
+            String::from_utf8_lossy(METAFILE).replace("VOR RWY 13L/13R", "PARKWAY VISUAL RWY 13");
+        let router = router_for(test_state(test_config(&[]), metafile.as_bytes()));
+        for (uri, pdf_name) in [
+            ("/v1/charts/JFK/visual", "00610V13L.PDF"),
+            ("/v1/charts/JFK/the visual", "00610V13L.PDF"),
+            ("/v1/charts/LGA/cir", "00519LDAA.PDF"),
+            ("/v1/charts/HWV/circle to land", "01301VA.PDF"),
+        ] {
+            let (status, headers, _) = send(
+                router.clone(),
+                Request::get(uri.replace(' ', "%20"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::TEMPORARY_REDIRECT, "{uri}");
+            let location = headers[header::LOCATION].to_str().unwrap();
+            assert!(location.ends_with(pdf_name), "{uri}: {location}");
+        }
+    }
+
+    #[tokio::test]
+    async fn current_cycle_is_read_from_json_or_xml_info() {
+        let faa = mock_faa(
+            Router::new()
+                .route(
+                    "/json",
+                    get(|| async {
+                        (
+                            [(header::CONTENT_TYPE, "application/json")],
+                            include_str!("../tests/fixtures/dtpp_info.json"),
+                        )
+                    }),
+                )
+                .route(
+                    "/xml",
+                    get(|| async {
+                        (
+                            [(header::CONTENT_TYPE, "application/xml")],
+                            include_str!("../tests/fixtures/dtpp_info.xml"),
+                        )
+                    }),
+                )
+                .route(
+                    "/cut-short",
+                    get(|| async {
+                        Body::from_stream(stream::iter([
+                            Ok(Bytes::from_static(b"<productSet>")),
+                            Err(std::io::Error::other("connection reset")),
+                        ]))
+                    }),
+                ),
+        )
+        .await;
+        let client = build_client(Duration::from_secs(5)).unwrap();
+        for format in ["json", "xml"] {
+            let (cycle, edition) = fetch_current_cycle(&client, &format!("{faa}/{format}"))
+                .await
+                .unwrap();
+            assert_eq!(cycle, "2410", "{format}");
+            assert_eq!(edition.number, "10", "{format}");
+            assert_eq!(edition.date, "10/03/2024", "{format}");
+            assert_eq!(edition.products.len(), 1, "{format}");
+            assert_eq!(
+                edition.products[0].name, "DIGITAL-TERMINAL PROCEDURES PUBLICATION",
+                "{format}"
+            );
+        }
+
+        // A body cut short is the FAA's failure like any other, not an unexpected error of ours
+        let e = fetch_current_cycle(&client, &format!("{faa}/cut-short"))
+            .await
+            .unwrap_err();
+        assert!(e.chain().any(<dyn std::error::Error>::is::<UpstreamError>));
+    }
+
+    #[tokio::test]
+    async fn chained_search_terms_use_the_first_match() {
+        let (status, headers, _) = send(
+            test_router(&[]),
+            Request::get("/v1/charts/JFK/NOPE+ALTERNATE")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(
+            headers[header::LOCATION],
+            "https://aeronav.faa.gov/d-tpp/2410/NE2ALT.PDF"
+        );
+
+        let (status, body) = get_json("/v1/charts/JFK/NOPE+NADA").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["status_code"], "404");
+    }
+
+    #[tokio::test]
+    async fn head_charts_matches_get_without_a_body() {
+        let (status, headers, body) = send(
+            test_router(&[]),
+            Request::head("/v1/charts?apt=JFK")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-cycle"], "2410");
+        assert!(body.is_empty());
+
+        let (get_status, _) = get_json("/v1/charts").await;
+        let (status, _, body) = send(
+            test_router(&[]),
+            Request::head("/v1/charts").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, get_status);
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn poisoned_app_state_keeps_serving() {
+        let state = test_state(test_config(&[]), METAFILE);
+        let app_state = Arc::clone(&state.app_state);
+        std::thread::spawn(move || {
+            let _writer = app_state.write().unwrap();
+            panic!("writer panicked");
+        })
+        .join()
+        .unwrap_err();
+        assert!(state.app_state.is_poisoned());
+
+        let (status, _, body) = send(
+            router_for(state),
+            Request::get("/v1/charts?apt=JFK")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(!body["JFK"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn diagram_pdf_is_proxied_with_its_filename() {
+        let pdf_host = mock_faa(Router::new().route(
+            "/:cycle/:pdf",
+            get(|Path((_, pdf)): Path<(String, String)>| async move { format!("%PDF {pdf}") }),
+        ))
+        .await;
+        let router = test_router(&[("CHARTSAPI_PDF_BASE_URL", &pdf_host)]);
+        let (status, headers, body) = send(
+            router.clone(),
+            Request::get("/v1/charts/KJFK/diagram.pdf")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/pdf");
+        assert_eq!(
+            headers[header::CONTENT_DISPOSITION],
+            "inline; filename=\"00610AD.PDF\""
+        );
+        assert_eq!(body, "%PDF 00610AD.PDF");
+
+        let (status, headers, body) = send(
+            router,
+            Request::get("/v1/charts/T76/diagram.pdf")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status_code"], "404");
+    }
+
+    #[tokio::test]
+    async fn trailing_slashes_are_trimmed() {
+        let app = NormalizePath::trim_trailing_slash(test_router(&[]));
+        for (uri, trimmed) in [
+            ("/v1/charts/?apt=JFK", "/v1/charts?apt=JFK"),
+            ("/v1/cycle/", "/v1/cycle"),
+        ] {
+            let mut bodies = Vec::new();
+            for uri in [uri, trimmed] {
+                let response = app
+                    .clone()
+                    .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK, "{uri}");
+                bodies.push(to_bytes(response.into_body(), usize::MAX).await.unwrap());
+            }
+            assert_eq!(bodies[0], bodies[1], "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn head_chart_search_checks_existence_without_a_body() {
+        let (status, headers, body) = send(
+            test_router(&[]),
+            Request::head("/v1/charts/JFK/ALTERNATE")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        // A 200 rather than the GET's redirect, so clients needn't follow it to learn the URL
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            headers[header::LOCATION],
+            "https://aeronav.faa.gov/d-tpp/2410/NE2ALT.PDF"
+        );
+        assert!(body.is_empty());
+
+        let (status, headers, body) = send(
+            test_router(&[]),
+            Request::head("/v1/charts/JFK/NOPE")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(!headers.contains_key(header::LOCATION));
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn filtered_out_airports_differ_from_unknown_ones() {
+        // T76 has no departures, and NOPE isn't an airport
+        let (status, body) = get_json("/v1/charts?apt=T76,NOPE&group=4").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!({"T76": []}));
+
+        let (status, body) = get_json("/v1/charts?apt=T76,NOPE&group=4&empty=list").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!({"empty": ["T76"]}));
+    }
+
+    #[tokio::test]
+    async fn per_airport_limit_truncates_before_grouping() {
+        let (status, body) = get_json("/v1/charts?apt=JFK,T76&group=1&per_airport_limit=3").await;
+        assert_eq!(status, StatusCode::OK);
+        // JFK's first three charts are all general pages, and T76 has only two charts
+        let names = |charts: &serde_json::Value| -> Vec<String> {
+            charts
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|chart| chart["chart_name"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(
+            names(&body["JFK"]["General"]),
+            ["TAKEOFF MINIMUMS", "ALTERNATE MINIMUMS", "HOT SPOT"]
+        );
+        assert_eq!(body["JFK"].as_object().unwrap().len(), 1);
+        let t76_charts: usize = body["T76"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|charts| charts.as_array().unwrap().len())
+            .sum();
+        assert_eq!(t76_charts, 2);
+        assert_eq!(body["truncated"], serde_json::json!(["JFK"]));
+    }
+
+    #[tokio::test]
+    async fn missing_static_files_are_json_errors() {
+        let (status, body) = get_json("/v1/charts/static/missing.png").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["status"], "error");
+        assert_eq!(body["status_code"], "404");
+    }
+
+    #[tokio::test]
+    async fn encoded_slashes_in_chart_names_stay_in_the_search() {
+        let (status, headers, _) = send(
+            test_router(&[]),
+            Request::get("/v1/charts/JFK/RWY%2013L%2F13R")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(
+            headers[header::LOCATION],
+            "https://aeronav.faa.gov/d-tpp/2410/00610V13L.PDF"
+        );
+
+        let (status, body) = get_json("/v1/charts/JFK/RWY%2013L%2F31R").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["status_code"], "404");
+    }
+
+    #[tokio::test]
+    async fn unknown_chart_codes_are_counted_once_and_served_as_general() {
+        let metafile = String::from_utf8_lossy(METAFILE).replace(
+            "<chart_code>HOT</chart_code>",
+            "<chart_code>BOGUS</chart_code>",
+        );
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let state = metrics::with_local_recorder(&recorder, || {
+            test_state(test_config(&[]), metafile.as_bytes())
+        });
+        // Both hot spot charts are BOGUS, but the new code is only reported once per load
+        assert!(recorder
+            .handle()
+            .render()
+            .contains(r#"chartsapi_unknown_chart_codes_total{code="BOGUS"} 1"#));
+
+        let router = router_for(state);
+        let (status, _, body) = send(
+            router.clone(),
+            Request::get("/v1/chart-codes").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let codes: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let bogus: Vec<_> = codes
+            .iter()
+            .filter(|code| code["code"] == "BOGUS")
+            .collect();
+        assert_eq!(
+            bogus,
+            [&serde_json::json!({"code": "BOGUS", "group": "General", "count": 2})]
+        );
+        assert!(!codes.iter().any(|code| code["code"] == "HOT"));
+
+        let (_, _, body) = send(
+            router,
+            Request::get("/v1/charts?apt=JFK")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let hot_spot = body["JFK"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|chart| chart["chart_code"] == "BOGUS")
+            .unwrap();
+        assert_eq!(hot_spot["chart_name"], "HOT SPOT");
+        assert_eq!(hot_spot["group"], "General");
+    }
+
+    #[tokio::test]
+    async fn charts_without_a_pdf_are_never_served() {
+        // The fixture's JFK "CONTINUED" record has an empty `pdf_name`
+        let (status, body) = get_json("/v1/charts?apt=JFK").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["JFK"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|chart| chart["chart_name"] != "CONTINUED" && chart["pdf_name"] != ""));
+
+        let (status, body) = get_json("/v1/charts/JFK/CONTINUED").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["status_code"], "404");
+    }
+
+    #[tokio::test]
+    async fn empty_metafiles_keep_the_previous_cycle() {
+        let config = test_config(&[]);
+        let state = test_state(config.clone(), METAFILE);
+        let empty = Metafile::new(
+            Bytes::from_static(
+                br#"<digital_tpp cycle="2411" from_edate="0901Z  10/31/24" to_edate="0901Z  11/28/24"></digital_tpp>"#,
+            ),
+            MetafileValidators::default(),
+        );
+        assert!(parse_charts(&config, "2411", &empty).is_err_and(|e| e.is::<TooFewCharts>()));
+
+        let edition = EditionDto {
+            name: "CURRENT".to_string(),
+            date: "10/31/2024".to_string(),
+            number: "11".to_string(),
+            geoname: "US".to_string(),
+            format: "ZIP".to_string(),
+            products: Vec::new(),
+        };
+        assert!(!replace_charts(
+            &state.app_state,
+            &config,
+            "2411",
+            &empty,
+            edition
+        ));
+        let (status, headers, body) = send(
+            router_for(state),
+            Request::get("/v1/charts?apt=JFK")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-cycle"], "2410");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(!body["JFK"].as_array().unwrap().is_empty());
+    }
+
+    /// A record's chart name, amendment number and amendment date
+    type Amendment<'a> = (&'a str, &'a str, &'a str);
+
+    /// A metafile of one airport per ident, each with a record per amendment
+    fn amendments_metafile(airports: &[(&str, &[Amendment])]) -> String {
+        use std::fmt::Write as _;
+        let mut xml = String::from(
+            r#"<digital_tpp cycle="2410" from_edate="0901Z  10/03/24" to_edate="0901Z  10/31/24"><state_code ID="NY" state_fullname="New York"><city_name ID="NEW YORK" volume="NE-2">"#,
+        );
+        for (index, (ident, amendments)) in airports.iter().enumerate() {
+            write!(
+                xml,
+                r#"<airport_name ID="{ident} AIRPORT" military="N" apt_ident="{ident}" icao_ident="K{ident}" alnum="{index}">"#
+            )
+            .unwrap();
+            for (name, number, date) in *amendments {
+                write!(
+                    xml,
+                    "<record><chartseq>50100</chartseq><chart_code>IAP</chart_code>\
+                     <chart_name>{name}</chart_name><useraction/>\
+                     <pdf_name>{ident}{name}.PDF</pdf_name><cn_flg>N</cn_flg><cnsection/>\
+                     <cnpage/><bvsection>C</bvsection><bvpage/><procuid/>\
+                     <two_colored>N</two_colored><civil/><faanfd18/><copter/>\
+                     <amdtnum>{number}</amdtnum><amdtdate>{date}</amdtdate></record>"
+                )
+                .unwrap();
+            }
+            xml.push_str("</airport_name>");
+        }
+        xml + "</city_name></state_code></digital_tpp>"
+    }
+
+    #[tokio::test]
+    async fn latest_amendment_returns_every_tied_chart() {
+        let metafile = amendments_metafile(&[
+            (
+                "AAA",
+                &[
+                    ("OLD", "1", "01/05/2023"),
+                    ("TIED ONE", "2", "09/05/2024"),
+                    ("BLANK", "", ""),
+                    ("TIED TWO", "3", "09/05/2024"),
+                    ("GARBAGE", "4", "soon"),
+                    ("OLDER", "5", "12/31/2022"),
+                ],
+            ),
+            (
+                "BBB",
+                &[("UNDATED", "1", ""), ("GARBAGE", "2", "13/45/2024")],
+            ),
+        ]);
+        let router = router_for(test_state(test_config(&[]), metafile.as_bytes()));
+        let get = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let (status, _, body) =
+                    send(router, Request::get(uri).body(Body::empty()).unwrap()).await;
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+                )
+            }
+        };
+
+        let (status, body) = get("/v1/charts/AAA/latest-amendment").await;
+        assert_eq!(status, StatusCode::OK);
+        let names: Vec<&str> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|chart| chart["chart_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["TIED ONE", "TIED TWO"]);
+
+        for uri in [
+            "/v1/charts/BBB/latest-amendment",
+            "/v1/charts/NOPE/latest-amendment",
+        ] {
+            let (status, body) = get(uri).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+            assert_eq!(body["status_code"], "404", "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn min_amendment_num_excludes_lower_and_unparseable_numbers() {
+        let metafile = amendments_metafile(&[(
+            "AAA",
+            &[
+                ("ONE", "1", ""),
+                ("TWO A", "2A", ""),
+                ("THREE", "3", ""),
+                ("ORIGINAL", "ORIG", ""),
+                ("BLANK", "", ""),
+            ],
+        )]);
+        let (status, _, body) = send(
+            router_for(test_state(test_config(&[]), metafile.as_bytes())),
+            Request::get("/v1/charts?apt=AAA&min_amendment_num=2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let names: Vec<&str> = body["AAA"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|chart| chart["chart_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["TWO A", "THREE"]);
+    }
+
+    #[tokio::test]
+    async fn a_one_typo_search_suggests_the_intended_chart() {
+        let (status, body) = get_json("/v1/charts/JFK/KENEDY%20FIVE?suggest=true").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["status_code"], "404");
+        assert_eq!(body["suggestions"][0], "KENNEDY FIVE");
+    }
+
+    #[tokio::test]
+    async fn groups_without_charts_are_omitted_unless_included() {
+        // T76 has an approach but no departures or arrivals
+        let (status, body) = get_json("/v1/charts?apt=T76&group=7").await;
+        assert_eq!(status, StatusCode::OK);
+        let groups: Vec<&String> = body["T76"].as_object().unwrap().keys().collect();
+        assert_eq!(groups, ["CAPP"]);
+
+        let (status, body) = get_json("/v1/charts?apt=T76&group=7&group_empty=include").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["T76"]["DP"], serde_json::json!([]));
+        assert_eq!(body["T76"]["STAR"], serde_json::json!([]));
+        assert_eq!(body["T76"]["CAPP"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn grouped_keys_follow_the_naming_mode() {
+        let group_keys = |body: &serde_json::Value| -> Vec<String> {
+            body["T76"].as_object().unwrap().keys().cloned().collect()
+        };
+        let (_, body) = get_json("/v1/charts?apt=T76&group=1").await;
+        assert_eq!(group_keys(&body), ["CAPP", "General"]);
+
+        let (status, body) = get_json("/v1/charts?apt=T76&group=1&naming=snake").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(group_keys(&body), ["approaches", "general"]);
+
+        let (status, body) = get_json("/v1/charts?apt=T76&group=1&group_format=labeled").await;
+        assert_eq!(status, StatusCode::OK);
+        let labels: Vec<(&str, &str)> = body["T76"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|group| {
+                (
+                    group["code"].as_str().unwrap(),
+                    group["label"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(labels, [("General", "General"), ("CAPP", "Approaches")]);
+    }
+
+    #[tokio::test]
+    async fn nested_base_path_serves_routes_and_links_under_it() {
+        let router = test_router(&[
+            ("CHARTSAPI_BASE_PATH", "/api/charts/"),
+            ("CHARTSAPI_NEST_BASE_PATH", "true"),
+        ]);
+        let (status, _, body) = send(
+            router.clone(),
+            Request::get("/api/charts/v1/charts?apt=JFK&hateoas=true")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let chart = &body["JFK"][0];
+        assert_eq!(chart["_links"]["charts"], "/api/charts/v1/charts/JFK");
+        // Links to the FAA's own PDFs are external, so never prefixed
+        assert_eq!(
+            chart["pdf_path"],
+            "https://aeronav.faa.gov/d-tpp/2410/NE2TO.PDF"
+        );
+
+        let (status, _, _) = send(
+            router,
+            Request::get("/v1/charts?apt=JFK")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn expanded_airports_are_serialized_once_per_entry() {
+        let (status, body) = get_json("/v1/charts?apt=JFK,T76&expand=airport").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["JFK"]["airport"],
+            serde_json::json!({
+                "ident": "JFK",
+                "icao": "KJFK",
+                "name": "JOHN F KENNEDY INTL",
+                "city": "NEW YORK",
+                "state": "NY",
+                "state_full": "New York",
+                "volume": "NE-2",
+                "military": false,
+            })
+        );
+        assert_eq!(body["T76"]["airport"]["icao"], serde_json::Value::Null);
+
+        let chart = body["JFK"]["charts"][0].as_object().unwrap();
+        assert_eq!(chart["chart_name"], "TAKEOFF MINIMUMS");
+        for field in [
+            "state",
+            "state_full",
+            "city",
+            "volume",
+            "airport_name",
+            "military",
+            "faa_ident",
+            "icao_ident",
+        ] {
+            assert!(!chart.contains_key(field), "{field}");
+        }
+    }
+
+    #[tokio::test]
+    async fn csv_export_filters_by_state_and_group() {
+        let (status, headers, body) = send(
+            test_router(&[]),
+            Request::get("/v1/charts.csv?state=NY&group=2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/csv"));
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let (header_row, rows) = body.split_at(csv_export::HEADER.len());
+        assert_eq!(header_row, csv_export::HEADER);
+        let rows: Vec<&str> = rows.split_terminator("\r\n").collect();
+        assert!(!rows.is_empty());
+        // State codes and groups never need quoting, so the first and last fields split cleanly
+        for row in &rows {
+            assert!(row.starts_with("NY,"), "{row}");
+            assert!(row.ends_with(",Apd"), "{row}");
+        }
+        assert!(rows.iter().any(|row| row.contains(",00610AD.PDF,")));
+    }
+
+    #[tokio::test]
+    async fn sqlite_export_reopens_with_every_airport_and_chart() {
+        let config = test_config(&[("CHARTSAPI_ADMIN_TOKEN", "secret")]);
+        let state = test_state(config, METAFILE);
+        let hashmaps = Arc::clone(&state.app_state.read().unwrap().hashmaps);
+
+        let (status, _, _) = send(
+            router_for(state.clone()),
+            Request::get("/v1/export.sqlite")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, headers, body) = send(
+            router_for(state),
+            Request::get("/v1/export.sqlite")
+                .header(header::AUTHORIZATION, "Bearer secret")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/vnd.sqlite3");
+
+        // SAFETY: the buffer comes from sqlite3_malloc64 and is filled before SQLite takes it over
+        let data = unsafe {
+            let ptr = rusqlite::ffi::sqlite3_malloc64(body.len() as u64).cast::<u8>();
+            let ptr = std::ptr::NonNull::new(ptr).unwrap();
+            std::ptr::copy_nonoverlapping(body.as_ptr(), ptr.as_ptr(), body.len());
+            rusqlite::serialize::OwnedData::from_raw_nonnull(ptr, body.len())
+        };
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.deserialize(rusqlite::DatabaseName::Main, data, true)
+            .unwrap();
+
+        let count = |sql: &str| {
+            conn.query_row(sql, [], |row| row.get::<_, usize>(0))
+                .unwrap()
+        };
+        assert_eq!(count("SELECT COUNT(*) FROM airports"), hashmaps.faa.len());
+        assert_eq!(
+            count("SELECT COUNT(*) FROM charts"),
+            hashmaps.faa.values().map(Vec::len).sum::<usize>()
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM charts LEFT JOIN airports USING (faa_ident) WHERE airports.faa_ident IS NULL"),
+            0
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM airports WHERE faa_ident = 'T76' AND icao_ident IS NULL"),
+            1
+        );
+        let mut indexes: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'index' AND sql IS NOT NULL")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        indexes.sort();
+        assert_eq!(
+            indexes,
+            [
+                "airports_icao_ident",
+                "charts_chart_code",
+                "charts_faa_ident"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn airport_info_is_attached_from_the_enrichment_file() {
+        let path = std::env::temp_dir().join(format!("chartsapi-info-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"jfk": {"elevation_ft": 13, "atis_frequency": "128.725"}}"#,
+        )
+        .unwrap();
+        let config = test_config(&[("CHARTSAPI_AIRPORT_INFO_FILE", path.to_str().unwrap())]);
+        let mut state = test_state(config, METAFILE);
+        state.enricher = load_enricher(&state.config.get());
+        std::fs::remove_file(&path).unwrap();
+        let router = router_for(state);
+
+        for uri in ["/v1/charts?apt=JFK", "/v1/charts?apt=JFK&airport_meta=true"] {
+            let (status, plain) = get_json(uri).await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            let (status, _, body) = send(
+                router.clone(),
+                Request::get(uri).body(Body::empty()).unwrap(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            let mut enriched: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            if uri.ends_with("airport_meta=true") {
+                assert_eq!(
+                    enriched["JFK"]["airport"]
+                        .as_object_mut()
+                        .unwrap()
+                        .remove("info"),
+                    Some(serde_json::json!({"elevation_ft": 13, "atis_frequency": "128.725"})),
+                    "{uri}"
+                );
+            }
+            assert_eq!(enriched, plain, "{uri}");
+        }
+    }
 }
@@ -1,6 +1,8 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Hash)]
 pub enum ChartGroup {
     General,
     Departures,
@@ -18,20 +20,164 @@ pub struct ChartDto {
     pub airport_name: String,
     pub military: String,
     pub faa_ident: String,
-    pub icao_ident: String,
+    /// The airport's place in its d-TPP volume, which only orders charts so isn't serialized
+    #[serde(skip)]
+    pub alnum: String,
+    /// Blank when the airport has no ICAO ident, and `None` only when omitted on request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icao_ident: Option<String>,
     pub chart_seq: String,
     pub chart_code: String,
     pub chart_name: String,
     pub pdf_name: String,
     pub pdf_path: String,
-    #[serde(skip_serializing)]
+    pub amendment_number: String,
+    pub amendment_date: String,
+    #[serde(default, skip_serializing_if = "PaginationDto::is_empty")]
+    pub pagination: PaginationDto,
+    /// Serialized as `group` so clients needn't replicate the `chart_code` mapping
+    #[serde(rename = "group")]
     pub chart_group: ChartGroup,
+    /// Only set when hypermedia links are requested
+    #[serde(rename = "_links", default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<ChartLinksDto>,
 }
 
+/// Hypermedia links from a chart to itself, its PDF through this server, and its airport's charts
 #[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChartLinksDto {
+    #[serde(rename = "self")]
+    pub chart: String,
+    pub pdf: String,
+    pub charts: String,
+}
+
+/// Change notice and bound volume cross-references into the printed d-TPP
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PaginationDto {
+    pub change_notice_section: String,
+    pub change_notice_page: String,
+    pub bound_volume_section: String,
+    pub bound_volume_page: String,
+}
+
+impl PaginationDto {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        [
+            &self.change_notice_section,
+            &self.change_notice_page,
+            &self.bound_volume_section,
+            &self.bound_volume_page,
+        ]
+        .iter()
+        .all(|s| s.trim().is_empty())
+    }
+}
+
+impl ChartDto {
+    /// Sort key for `chart_seq`, which is not always numeric. Numeric sequences sort first by value
+    /// (so "007" is 7), then non-numeric sequences lexically, then blank sequences.
+    #[must_use]
+    pub fn chart_seq_key(&self) -> (u8, u64, &str) {
+        let seq = self.chart_seq.trim();
+        match seq.parse::<u64>() {
+            Ok(number) => (0, number, seq),
+            Err(_) if seq.is_empty() => (2, 0, seq),
+            Err(_) => (1, 0, seq),
+        }
+    }
+
+    /// Sort key for the printed d-TPP order of charts: general pages, approaches, the airport
+    /// diagram, departures and then arrivals, each by the airport's `alnum` place in its volume
+    /// and then by [`Self::chart_seq_key`]. Airports without a numeric `alnum` sort last.
+    #[must_use]
+    pub fn book_order_key(&self) -> (u8, u64, (u8, u64, &str)) {
+        let rank = match self.chart_group {
+            ChartGroup::General => 0,
+            ChartGroup::Approaches => 1,
+            ChartGroup::Apd => 2,
+            ChartGroup::Departures => 3,
+            ChartGroup::Arrivals => 4,
+        };
+        let alnum = self.alnum.trim().parse().unwrap_or(u64::MAX);
+        (rank, alnum, self.chart_seq_key())
+    }
+
+    /// Parses the FAA `MM/DD/YYYY` amendment date, returning `None` when blank or malformed
+    #[must_use]
+    pub fn parsed_amendment_date(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(&self.amendment_date, "%m/%d/%Y").ok()
+    }
+
+    /// Parses the leading number of the FAA amendment number, so "2A" yields 2, returning `None`
+    /// when it has none, such as when blank
+    #[must_use]
+    pub fn parsed_amendment_number(&self) -> Option<u32> {
+        let number = self.amendment_number.trim();
+        number[..number
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(number.len())]
+            .parse()
+            .ok()
+    }
+
+    /// Every runway named after `RWY`/`RWYS` in the chart name, so "ILS RWY 4L/22R" yields `4L` and
+    /// `22R` and "RWY 28L/R" yields `28L` and `28R`. Runways are normalized by [`parse_runway`].
+    #[must_use]
+    pub fn runways(&self) -> Vec<String> {
+        let mut runways = Vec::new();
+        let mut tokens = self.chart_name.split_whitespace();
+        while let Some(token) = tokens.next() {
+            if token != "RWY" && token != "RWYS" {
+                continue;
+            }
+            let Some(list) = tokens.next() else {
+                break;
+            };
+            let mut number = String::new();
+            for part in list
+                .trim_end_matches(|c: char| !c.is_ascii_alphanumeric())
+                .split('/')
+            {
+                // A bare side such as the `R` in `28L/R` shares the preceding runway number
+                let part = match part {
+                    "L" | "C" | "R" => format!("{number}{part}"),
+                    _ => part.to_string(),
+                };
+                if let Some(runway) = parse_runway(&part) {
+                    number = runway.trim_end_matches(['L', 'C', 'R']).to_string();
+                    if !runways.contains(&runway) {
+                        runways.push(runway);
+                    }
+                }
+            }
+        }
+        runways
+    }
+}
+
+/// Normalizes a runway designator such as `04l` to `4L`, returning `None` unless it is a runway
+/// number from 1 to 36 with an optional `L`, `C` or `R` side
+#[must_use]
+pub fn parse_runway(runway: &str) -> Option<String> {
+    let runway = runway.trim().to_uppercase();
+    let (number, side) = runway.split_at(runway.trim_end_matches(['L', 'C', 'R']).len());
+    if number.is_empty() || number.len() > 2 || side.len() > 1 {
+        return None;
+    }
+    let number = number.parse::<u8>().ok().filter(|n| (1..=36).contains(n))?;
+    Some(format!("{number}{side}"))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct GroupedChartsDto {
     #[serde(rename = "General", skip_serializing_if = "Option::is_none")]
     pub general: Option<Vec<ChartDto>>,
+    /// Airport diagrams, only kept apart from `General` by `group=0`
+    #[serde(rename = "APD", skip_serializing_if = "Option::is_none")]
+    pub apd: Option<Vec<ChartDto>>,
     #[serde(rename = "DP", skip_serializing_if = "Option::is_none")]
     pub departures: Option<Vec<ChartDto>>,
     #[serde(rename = "STAR", skip_serializing_if = "Option::is_none")]
@@ -40,16 +186,36 @@ pub struct GroupedChartsDto {
     pub approaches: Option<Vec<ChartDto>>,
 }
 
+impl Default for GroupedChartsDto {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl GroupedChartsDto {
+    #[must_use]
+    pub const fn groups(&self) -> [&Option<Vec<ChartDto>>; 5] {
+        [
+            &self.general,
+            &self.apd,
+            &self.departures,
+            &self.arrivals,
+            &self.approaches,
+        ]
+    }
+
+    #[must_use]
     pub const fn new() -> Self {
         Self {
             general: None,
+            apd: None,
             departures: None,
             arrivals: None,
             approaches: None,
         }
     }
 
+    /// Appends to the chart's group, so each group keeps the order of the flat list it came from
     pub fn add_chart(&mut self, chart_dto: ChartDto) {
         let charts_category_vec = match &chart_dto.chart_group {
             ChartGroup::General | ChartGroup::Apd => &mut self.general,
@@ -57,16 +223,730 @@ impl GroupedChartsDto {
             ChartGroup::Arrivals => &mut self.arrivals,
             ChartGroup::Approaches => &mut self.approaches,
         };
-        match charts_category_vec {
-            Some(ref mut charts) => charts.push(chart_dto),
-            None => *charts_category_vec = Some(vec![chart_dto]),
+        push_chart(charts_category_vec, chart_dto);
+    }
+
+    /// Appends to the chart's own group like [`Self::add_chart`], but with airport diagrams in
+    /// `APD` rather than `General`, so each [`ChartGroup`] has exactly one bucket
+    pub fn add_chart_by_group(&mut self, chart_dto: ChartDto) {
+        if chart_dto.chart_group == ChartGroup::Apd {
+            push_chart(&mut self.apd, chart_dto);
+        } else {
+            self.add_chart(chart_dto);
+        }
+    }
+
+    /// Replaces absent groups with empty lists so every group key is serialized, including `APD`
+    /// when airport diagrams have their own group
+    pub fn include_empty_groups(&mut self, with_apd: bool) {
+        for charts in [
+            &mut self.general,
+            &mut self.departures,
+            &mut self.arrivals,
+            &mut self.approaches,
+        ] {
+            charts.get_or_insert_with(Vec::new);
+        }
+        if with_apd {
+            self.apd.get_or_insert_with(Vec::new);
+        }
+    }
+
+    /// Converts to the labeled representation, keeping the compatibility keys as each `code`
+    #[must_use]
+    pub fn into_labeled(self) -> Vec<LabeledGroupDto> {
+        [
+            ("General", "General", self.general),
+            ("APD", "Airport Diagrams", self.apd),
+            ("DP", "Departures", self.departures),
+            ("STAR", "Arrivals", self.arrivals),
+            ("CAPP", "Approaches", self.approaches),
+        ]
+        .into_iter()
+        .filter_map(|(code, label, charts)| {
+            charts.map(|charts| LabeledGroupDto {
+                code: code.to_string(),
+                label: label.to_string(),
+                charts,
+            })
+        })
+        .collect()
+    }
+}
+
+fn push_chart(charts: &mut Option<Vec<ChartDto>>, chart_dto: ChartDto) {
+    match charts {
+        Some(ref mut charts) => charts.push(chart_dto),
+        None => *charts = Some(vec![chart_dto]),
+    }
+}
+
+/// Grouped charts keyed in snake case like the chart fields, rather than by the upstream codes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnakeCaseGroupsDto {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub general: Option<Vec<ChartDto>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apd: Option<Vec<ChartDto>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub departures: Option<Vec<ChartDto>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arrivals: Option<Vec<ChartDto>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approaches: Option<Vec<ChartDto>>,
+}
+
+impl SnakeCaseGroupsDto {
+    #[must_use]
+    pub const fn groups(&self) -> [&Option<Vec<ChartDto>>; 5] {
+        [
+            &self.general,
+            &self.apd,
+            &self.departures,
+            &self.arrivals,
+            &self.approaches,
+        ]
+    }
+}
+
+impl From<GroupedChartsDto> for SnakeCaseGroupsDto {
+    fn from(grouped: GroupedChartsDto) -> Self {
+        Self {
+            general: grouped.general,
+            apd: grouped.apd,
+            departures: grouped.departures,
+            arrivals: grouped.arrivals,
+            approaches: grouped.approaches,
         }
     }
 }
 
+/// The flat chart list alongside the same charts grouped, as requested with `shape=both`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BothShapesDto {
+    pub charts: Vec<ChartDto>,
+    pub grouped: Box<ResponseDto>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LabeledGroupDto {
+    pub code: String,
+    pub label: String,
+    pub charts: Vec<ChartDto>,
+}
+
+/// Untagged to keep the upstream response shapes.
+///
+/// Deserialization picks the first variant that fits. Flat lists are arrays and the other shapes
+/// are objects. Both shapes come first as they hold grouped charts, which reject their `charts`
+/// key, and grouped charts reject the other naming's keys, so with charts every shape reads back as
+/// itself. Without any, some shapes serialize alike: grouped charts without groups are `{}` in
+/// either naming and read back as [`Self::GroupedCharts`], and an empty labeled list reads back as
+/// an empty flat list. Those hold no charts either way, so a discriminator isn't worth breaking the
+/// wire format for.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ResponseDto {
     Charts(Vec<ChartDto>),
+    Both(BothShapesDto),
     GroupedCharts(GroupedChartsDto),
+    SnakeCaseGroups(SnakeCaseGroupsDto),
+    LabeledGroups(Vec<LabeledGroupDto>),
+}
+
+impl ResponseDto {
+    #[must_use]
+    pub fn chart_count(&self) -> usize {
+        match self {
+            Self::Charts(charts) => charts.len(),
+            Self::Both(both) => both.charts.len(),
+            Self::GroupedCharts(grouped) => count_grouped(&grouped.groups()),
+            Self::SnakeCaseGroups(grouped) => count_grouped(&grouped.groups()),
+            Self::LabeledGroups(groups) => groups.iter().map(|group| group.charts.len()).sum(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.chart_count() == 0
+    }
+
+    /// The charts as a flat list, with grouped charts listed group by group
+    #[must_use]
+    pub fn into_charts(self) -> Vec<ChartDto> {
+        match self {
+            Self::Charts(charts) => charts,
+            Self::Both(both) => both.charts,
+            Self::GroupedCharts(grouped) => [
+                grouped.general,
+                grouped.apd,
+                grouped.departures,
+                grouped.arrivals,
+                grouped.approaches,
+            ]
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect(),
+            Self::SnakeCaseGroups(grouped) => [
+                grouped.general,
+                grouped.apd,
+                grouped.departures,
+                grouped.arrivals,
+                grouped.approaches,
+            ]
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect(),
+            Self::LabeledGroups(groups) => {
+                groups.into_iter().flat_map(|group| group.charts).collect()
+            }
+        }
+    }
+}
+
+fn count_grouped(groups: &[&Option<Vec<ChartDto>>]) -> usize {
+    groups
+        .iter()
+        .map(|charts| charts.as_ref().map_or(0, Vec::len))
+        .sum()
+}
+
+/// An airport's charts, either in the compatible repetitive shape or with the airport metadata
+/// hoisted into `airport` and omitted from each chart
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AirportResponseDto {
+    Charts(ResponseDto),
+    WithAirport(AirportChartsDto),
+    Expanded(ExpandedAirportChartsDto),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AirportChartsDto {
+    pub airport: AirportMetaDto,
+    /// Same shape as the plain response, minus the fields in `airport`
+    pub charts: serde_json::Value,
+}
+
+/// Fields shared by every chart of an airport
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AirportMetaDto {
+    pub state: String,
+    pub state_full: String,
+    pub city: String,
+    pub volume: String,
+    pub airport_name: String,
+    pub military: String,
+    pub faa_ident: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icao_ident: Option<String>,
+    /// Only present when an enrichment source is configured and knows the airport
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<AirportInfoDto>,
+}
+
+/// Airport details from an optional enrichment source rather than the metafile
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AirportInfoDto {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elevation_ft: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atis_frequency: Option<String>,
+    /// ASOS or AWOS frequency
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weather_frequency: Option<String>,
+}
+
+impl AirportMetaDto {
+    /// Chart fields hoisted into the airport metadata
+    pub const FIELDS: [&'static str; 8] = [
+        "state",
+        "state_full",
+        "city",
+        "volume",
+        "airport_name",
+        "military",
+        "faa_ident",
+        "icao_ident",
+    ];
+
+    #[must_use]
+    pub fn from_chart(chart: &ChartDto) -> Self {
+        Self {
+            state: chart.state.clone(),
+            state_full: chart.state_full.clone(),
+            city: chart.city.clone(),
+            volume: chart.volume.clone(),
+            airport_name: chart.airport_name.clone(),
+            military: chart.military.clone(),
+            faa_ident: chart.faa_ident.clone(),
+            icao_ident: chart.icao_ident.clone(),
+            info: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExpandedAirportChartsDto {
+    pub airport: ExpandedAirportDto,
+    /// Same shape as the plain response, minus the airport fields of each chart
+    pub charts: serde_json::Value,
+}
+
+/// The airport of a set of charts as a structured object, rather than the metafile's strings
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExpandedAirportDto {
+    pub ident: String,
+    /// `None` when the airport has no ICAO ident or it was omitted on request
+    pub icao: Option<String>,
+    pub name: String,
+    pub city: String,
+    pub state: String,
+    pub state_full: String,
+    pub volume: String,
+    pub military: bool,
+    /// Only present when an enrichment source is configured and knows the airport
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<AirportInfoDto>,
+}
+
+impl ExpandedAirportDto {
+    #[must_use]
+    pub fn from_chart(chart: &ChartDto) -> Self {
+        Self {
+            ident: chart.faa_ident.clone(),
+            icao: chart.icao_ident.clone().filter(|icao| !icao.is_empty()),
+            name: chart.airport_name.clone(),
+            city: chart.city.clone(),
+            state: chart.state.clone(),
+            state_full: chart.state_full.clone(),
+            volume: chart.volume.clone(),
+            military: chart.military == "Y",
+            info: None,
+        }
+    }
+}
+
+/// Charts keyed by the requested airport ident, plus the airports without charts or cut short if
+/// requested
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChartsResponseDto {
+    #[serde(flatten)]
+    pub airports: IndexMap<String, AirportResponseDto>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub empty: Option<Vec<String>>,
+    /// Airports cut short by `per_airport_limit`, present whenever a limit was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<Vec<String>>,
+    /// Airports keyed by their FAA ident for want of an ICAO one, present whenever `key=icao`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub without_icao: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<DebugDto>,
+}
+
+/// Diagnostics for support, only included when explicitly requested
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DebugDto {
+    pub cycle: String,
+    pub airports: IndexMap<String, AirportDebugDto>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AirportDebugDto {
+    pub lookup: LookupPath,
+    pub charts_before_filtering: usize,
+    pub charts_after_filtering: usize,
+    /// Whether the airport's response without options is pre-warmed for the current cycle
+    pub prewarmed: bool,
+}
+
+/// Which map resolved a requested ident
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LookupPath {
+    Faa,
+    Icao,
+    NotFound,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChartCountsDto {
+    pub total: usize,
+    pub groups: IndexMap<ChartGroup, usize>,
+    pub states: IndexMap<String, usize>,
+}
+
+impl ChartCountsDto {
+    pub fn add_chart(&mut self, chart_dto: &ChartDto) {
+        self.total += 1;
+        *self
+            .groups
+            .entry(chart_dto.chart_group.clone())
+            .or_insert(0) += 1;
+        *self.states.entry(chart_dto.state.clone()).or_insert(0) += 1;
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AirportCountsDto {
+    pub total: usize,
+    pub military: usize,
+    pub with_icao: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatsDto {
+    pub cycle: String,
+    pub from_effective_date: DateTime<Utc>,
+    pub to_effective_date: DateTime<Utc>,
+    pub airports: AirportCountsDto,
+    pub charts: ChartCountsDto,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CycleDto {
+    pub cycle: String,
+    pub from_effective_date: DateTime<Utc>,
+    pub to_effective_date: DateTime<Utc>,
+    pub loaded_at: DateTime<Utc>,
+    pub chart_counts: ChartCountsDto,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edition: Option<EditionDto>,
+}
+
+/// The FAA's official edition details for the current cycle, as published by its info endpoint
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EditionDto {
+    pub name: String,
+    pub date: String,
+    pub number: String,
+    pub geoname: String,
+    pub format: String,
+    pub products: Vec<ProductDto>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProductDto {
+    pub name: String,
+    pub url: String,
+}
+
+/// A `group` code, whether it produces grouped output and the chart groups it includes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GroupCodeDto {
+    pub code: i32,
+    pub grouped: bool,
+    pub groups: Vec<ChartGroup>,
+}
+
+/// Log filter directives, as requested and as applied by the admin endpoint
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogFilterDto {
+    pub filter: String,
+}
+
+/// Outcome of resolving one requested ident, with `ident` echoing the input as given
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResolvedIdentDto {
+    pub ident: String,
+    pub found: bool,
+    pub faa_ident: Option<String>,
+    pub icao_ident: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResolvedIdentsDto {
+    pub idents: Vec<ResolvedIdentDto>,
+}
+
+/// A raw FAA chart code seen in the loaded data, with the group it is served under
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChartCodeDto {
+    pub code: String,
+    pub group: ChartGroup,
+    pub count: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PdfLinkDto {
+    pub chart_name: String,
+    pub pdf_path: String,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A chart identified by its position in the input through `pdf_name`
+    pub fn chart(index: usize, chart_group: ChartGroup) -> ChartDto {
+        ChartDto {
+            state: "NY".to_string(),
+            state_full: "New York".to_string(),
+            city: "NEW YORK".to_string(),
+            volume: "NE-2".to_string(),
+            airport_name: "JOHN F KENNEDY INTL".to_string(),
+            military: "N".to_string(),
+            faa_ident: "JFK".to_string(),
+            alnum: "610".to_string(),
+            icao_ident: Some("KJFK".to_string()),
+            chart_seq: index.to_string(),
+            chart_code: String::new(),
+            chart_name: format!("CHART {index}"),
+            pdf_name: index.to_string(),
+            pdf_path: String::new(),
+            amendment_number: String::new(),
+            amendment_date: String::new(),
+            pagination: PaginationDto::default(),
+            chart_group,
+            links: None,
+        }
+    }
+
+    fn round_trip(response: &ResponseDto) -> ResponseDto {
+        let json = serde_json::to_string(response).unwrap();
+        let read = serde_json::from_str::<ResponseDto>(&json).unwrap();
+        assert_eq!(serde_json::to_string(&read).unwrap(), json);
+        read
+    }
+
+    fn grouped() -> GroupedChartsDto {
+        let mut grouped = GroupedChartsDto::new();
+        for (index, group) in [
+            ChartGroup::General,
+            ChartGroup::Apd,
+            ChartGroup::Departures,
+            ChartGroup::Arrivals,
+            ChartGroup::Approaches,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            grouped.add_chart_by_group(chart(index, group));
+        }
+        grouped
+    }
+
+    #[test]
+    fn flat_charts_round_trip() {
+        let charts = ResponseDto::Charts(vec![chart(0, ChartGroup::General)]);
+        assert!(matches!(round_trip(&charts), ResponseDto::Charts(c) if c.len() == 1));
+        let empty = ResponseDto::Charts(vec![]);
+        assert!(matches!(round_trip(&empty), ResponseDto::Charts(c) if c.is_empty()));
+    }
+
+    #[test]
+    fn grouped_charts_round_trip() {
+        let read = round_trip(&ResponseDto::GroupedCharts(grouped()));
+        assert!(matches!(read, ResponseDto::GroupedCharts(_)));
+        assert_eq!(read.chart_count(), 5);
+
+        let mut empty_groups = GroupedChartsDto::new();
+        empty_groups.include_empty_groups(true);
+        let read = round_trip(&ResponseDto::GroupedCharts(empty_groups));
+        assert!(
+            matches!(read, ResponseDto::GroupedCharts(g) if g.groups().iter().all(|c| c.as_ref().is_some_and(Vec::is_empty)))
+        );
+    }
+
+    #[test]
+    fn snake_case_groups_round_trip() {
+        let read = round_trip(&ResponseDto::SnakeCaseGroups(grouped().into()));
+        assert!(matches!(read, ResponseDto::SnakeCaseGroups(_)));
+        assert_eq!(read.chart_count(), 5);
+
+        let mut empty_groups = GroupedChartsDto::new();
+        empty_groups.include_empty_groups(false);
+        let read = round_trip(&ResponseDto::SnakeCaseGroups(empty_groups.into()));
+        assert!(matches!(read, ResponseDto::SnakeCaseGroups(_)));
+    }
+
+    #[test]
+    fn labeled_groups_round_trip() {
+        let read = round_trip(&ResponseDto::LabeledGroups(grouped().into_labeled()));
+        assert!(matches!(read, ResponseDto::LabeledGroups(g) if g.len() == 5));
+
+        let mut empty_groups = GroupedChartsDto::new();
+        empty_groups.include_empty_groups(false);
+        let read = round_trip(&ResponseDto::LabeledGroups(empty_groups.into_labeled()));
+        assert!(matches!(read, ResponseDto::LabeledGroups(g) if g.len() == 4));
+    }
+
+    #[test]
+    fn both_shapes_round_trip() {
+        let both = ResponseDto::Both(BothShapesDto {
+            charts: ResponseDto::GroupedCharts(grouped()).into_charts(),
+            grouped: Box::new(ResponseDto::GroupedCharts(grouped())),
+        });
+        let ResponseDto::Both(read) = round_trip(&both) else {
+            panic!("both shapes read back as another shape");
+        };
+        assert!(matches!(*read.grouped, ResponseDto::GroupedCharts(_)));
+
+        let empty = ResponseDto::Both(BothShapesDto {
+            charts: vec![],
+            grouped: Box::new(ResponseDto::GroupedCharts(GroupedChartsDto::new())),
+        });
+        assert!(matches!(round_trip(&empty), ResponseDto::Both(_)));
+    }
+
+    /// The shapes documented on [`ResponseDto`] as reading back as another shape without charts
+    #[test]
+    fn shapes_without_charts_read_back_alike() {
+        let no_groups = GroupedChartsDto::new();
+        assert!(matches!(
+            round_trip(&ResponseDto::GroupedCharts(no_groups.clone())),
+            ResponseDto::GroupedCharts(g) if g.groups().iter().all(|c| c.is_none())
+        ));
+        assert!(matches!(
+            round_trip(&ResponseDto::SnakeCaseGroups(no_groups.clone().into())),
+            ResponseDto::GroupedCharts(g) if g.groups().iter().all(|c| c.is_none())
+        ));
+        assert!(matches!(
+            round_trip(&ResponseDto::LabeledGroups(no_groups.into_labeled())),
+            ResponseDto::Charts(c) if c.is_empty()
+        ));
+    }
+
+    fn with_seq(chart_seq: &str) -> ChartDto {
+        ChartDto {
+            chart_seq: chart_seq.to_string(),
+            ..chart(0, ChartGroup::General)
+        }
+    }
+
+    #[test]
+    fn chart_seq_key_orders_numbers_then_text_then_blanks() {
+        assert_eq!(with_seq("007").chart_seq_key(), (0, 7, "007"));
+        assert_eq!(with_seq(" 10100 ").chart_seq_key(), (0, 10100, "10100"));
+        assert_eq!(with_seq("A1").chart_seq_key(), (1, 0, "A1"));
+        assert_eq!(with_seq("").chart_seq_key(), (2, 0, ""));
+        assert_eq!(with_seq("  ").chart_seq_key(), (2, 0, ""));
+
+        let mut charts: Vec<ChartDto> = ["", "A1", "10", "007", "B", "8"]
+            .into_iter()
+            .map(with_seq)
+            .collect();
+        charts.sort_by(|a, b| a.chart_seq_key().cmp(&b.chart_seq_key()));
+        let order: Vec<&str> = charts.iter().map(|c| c.chart_seq.as_str()).collect();
+        assert_eq!(order, ["007", "8", "10", "A1", "B", ""]);
+    }
+
+    #[test]
+    fn book_order_key_orders_by_group_then_alnum_then_seq() {
+        let book_chart = |alnum: &str, chart_seq: &str, chart_group: ChartGroup| ChartDto {
+            alnum: alnum.to_string(),
+            chart_seq: chart_seq.to_string(),
+            ..chart(0, chart_group)
+        };
+        let mut charts = [
+            book_chart("610", "10", ChartGroup::Departures),
+            book_chart("610", "20", ChartGroup::Approaches),
+            book_chart("", "1", ChartGroup::Approaches),
+            book_chart("95", "30", ChartGroup::Approaches),
+            book_chart("610", "5", ChartGroup::General),
+        ];
+        charts.sort_by(|a, b| a.book_order_key().cmp(&b.book_order_key()));
+        let order: Vec<(&str, &str)> = charts
+            .iter()
+            .map(|c| (c.alnum.as_str(), c.chart_seq.as_str()))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                ("610", "5"),
+                ("95", "30"),
+                ("610", "20"),
+                ("", "1"),
+                ("610", "10")
+            ]
+        );
+    }
+
+    #[test]
+    fn chart_group_round_trips_as_group() {
+        let json = serde_json::to_value(chart(0, ChartGroup::Approaches)).unwrap();
+        assert_eq!(json["group"], "Approaches");
+        assert!(json.get("chart_group").is_none());
+        let read: ChartDto = serde_json::from_value(json).unwrap();
+        assert_eq!(read.chart_group, ChartGroup::Approaches);
+    }
+
+    #[test]
+    fn blank_pagination_is_omitted() {
+        let json = serde_json::to_value(chart(0, ChartGroup::General)).unwrap();
+        assert!(json.get("pagination").is_none());
+
+        let paginated = ChartDto {
+            pagination: PaginationDto {
+                bound_volume_section: "C".to_string(),
+                ..PaginationDto::default()
+            },
+            ..chart(0, ChartGroup::General)
+        };
+        let json = serde_json::to_value(paginated).unwrap();
+        assert_eq!(
+            json["pagination"],
+            serde_json::json!({
+                "change_notice_section": "",
+                "change_notice_page": "",
+                "bound_volume_section": "C",
+                "bound_volume_page": "",
+            })
+        );
+    }
+
+    #[test]
+    fn parsed_amendment_number_reads_the_leading_number() {
+        for (number, parsed) in [
+            ("3", Some(3)),
+            (" 12 ", Some(12)),
+            ("2A", Some(2)),
+            ("ORIG", None),
+            ("A2", None),
+            ("", None),
+            ("  ", None),
+        ] {
+            let chart = ChartDto {
+                amendment_number: number.to_string(),
+                ..chart(0, ChartGroup::Approaches)
+            };
+            assert_eq!(chart.parsed_amendment_number(), parsed, "{number:?}");
+        }
+    }
+
+    #[test]
+    fn parse_runway_normalizes_designators() {
+        assert_eq!(parse_runway("04l").as_deref(), Some("4L"));
+        assert_eq!(parse_runway(" 36 ").as_deref(), Some("36"));
+        assert_eq!(parse_runway("22C").as_deref(), Some("22C"));
+        for invalid in ["", "L", "0", "37", "100", "4LR", "4X", "-4"] {
+            assert_eq!(parse_runway(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn runways_reads_single_dual_and_slash_names() {
+        for (name, runways) in [
+            ("ILS OR LOC RWY 04L", &["4L"][..]),
+            ("VOR RWY 13L, CONT.1", &["13L"]),
+            ("ILS RWY 4L/22R", &["4L", "22R"]),
+            ("RNAV (GPS) RWY 28L/R", &["28L", "28R"]),
+            ("RNAV (GPS) RWYS 4L/C/R", &["4L", "4C", "4R"]),
+            ("ILS RWY 4 AND RWY 22", &["4", "22"]),
+            ("ILS RWY 4 OR RWY 04", &["4"]),
+            ("TAKEOFF MINIMUMS", &[]),
+            ("VOR RWY", &[]),
+            ("VOR RWY 37", &[]),
+        ] {
+            let chart = ChartDto {
+                chart_name: name.to_string(),
+                ..chart(0, ChartGroup::Approaches)
+            };
+            assert_eq!(chart.runways(), runways, "{name}");
+        }
+    }
 }
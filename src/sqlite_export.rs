@@ -0,0 +1,95 @@
+use crate::response_dtos::ChartDto;
+use indexmap::IndexMap;
+use rusqlite::{params, Connection, DatabaseName};
+
+const SCHEMA: &str = "
+CREATE TABLE airports (
+    faa_ident TEXT PRIMARY KEY,
+    icao_ident TEXT,
+    airport_name TEXT NOT NULL,
+    city TEXT NOT NULL,
+    state TEXT NOT NULL,
+    state_full TEXT NOT NULL,
+    volume TEXT NOT NULL,
+    military INTEGER NOT NULL
+);
+CREATE TABLE charts (
+    id INTEGER PRIMARY KEY,
+    faa_ident TEXT NOT NULL REFERENCES airports (faa_ident),
+    chart_seq TEXT NOT NULL,
+    chart_code TEXT NOT NULL,
+    chart_name TEXT NOT NULL,
+    chart_group TEXT NOT NULL,
+    pdf_name TEXT NOT NULL,
+    pdf_path TEXT NOT NULL,
+    amendment_number TEXT NOT NULL,
+    amendment_date TEXT NOT NULL,
+    change_notice_section TEXT NOT NULL,
+    change_notice_page TEXT NOT NULL,
+    bound_volume_section TEXT NOT NULL,
+    bound_volume_page TEXT NOT NULL
+);
+CREATE INDEX airports_icao_ident ON airports (icao_ident);
+CREATE INDEX charts_faa_ident ON charts (faa_ident);
+CREATE INDEX charts_chart_code ON charts (chart_code);
+";
+
+/// Builds an in-memory `SQLite` database of the airports and their charts, returning its file
+/// contents. Airport fields are stored once in `airports` rather than on every chart, with a blank
+/// ICAO ident as `NULL` and `military` as 0 or 1.
+pub fn export(airports: &IndexMap<String, Vec<ChartDto>>) -> rusqlite::Result<Vec<u8>> {
+    let mut conn = Connection::open_in_memory()?;
+    conn.execute_batch(SCHEMA)?;
+
+    let tx = conn.transaction()?;
+    {
+        let mut insert_airport = tx.prepare(
+            "INSERT INTO airports (faa_ident, icao_ident, airport_name, city, state, state_full, \
+             volume, military) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        let mut insert_chart = tx.prepare(
+            "INSERT INTO charts (faa_ident, chart_seq, chart_code, chart_name, chart_group, \
+             pdf_name, pdf_path, amendment_number, amendment_date, change_notice_section, \
+             change_notice_page, bound_volume_section, bound_volume_page) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        )?;
+        for (faa_ident, charts) in airports {
+            let Some(airport) = charts.first() else {
+                continue;
+            };
+            insert_airport.execute(params![
+                faa_ident,
+                airport
+                    .icao_ident
+                    .as_deref()
+                    .filter(|icao| !icao.is_empty()),
+                airport.airport_name,
+                airport.city,
+                airport.state,
+                airport.state_full,
+                airport.volume,
+                airport.military == "Y",
+            ])?;
+            for chart in charts {
+                insert_chart.execute(params![
+                    faa_ident,
+                    chart.chart_seq,
+                    chart.chart_code,
+                    chart.chart_name,
+                    format!("{:?}", chart.chart_group),
+                    chart.pdf_name,
+                    chart.pdf_path,
+                    chart.amendment_number,
+                    chart.amendment_date,
+                    chart.pagination.change_notice_section,
+                    chart.pagination.change_notice_page,
+                    chart.pagination.bound_volume_section,
+                    chart.pagination.bound_volume_page,
+                ])?;
+            }
+        }
+    }
+    tx.commit()?;
+
+    Ok(conn.serialize(DatabaseName::Main)?.to_vec())
+}
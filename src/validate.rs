@@ -0,0 +1,307 @@
+use crate::config::Config;
+use crate::faa_metafile::{decode_document, MetafileHeader, State, StateReader};
+use crate::{build_client, parse_effective_date};
+use chartsapi_rs::charts::build_hashmaps;
+use chrono::Utc;
+use std::collections::HashSet;
+use std::process::ExitCode;
+
+/// Runs the metafile parse and map-build pipeline against a local file or URL without starting
+/// the server, printing counts and anomalies. Fails when the metafile could not be used to serve.
+pub async fn run(config: &Config, source: &str) -> ExitCode {
+    let metafile = match read_metafile(config, source).await {
+        Ok(metafile) => metafile,
+        Err(e) => {
+            println!("FATAL: could not read metafile from {source}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    // Streamed like loading for the server, so what validates is what would be served
+    let (states, dtpp) = match StateReader::new(&metafile) {
+        Ok(reader) => reader,
+        Err(e) => {
+            println!("FATAL: could not parse metafile: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("Cycle: {}", dtpp.cycle);
+    let mut anomalies = Anomalies::default();
+    let mut fatal = check_effective_dates(config, &dtpp, &mut anomalies.found);
+
+    let mut error = None;
+    let hashmaps = build_hashmaps(
+        config,
+        &dtpp.cycle,
+        states
+            .map_while(|state| state.map_err(|e| error = Some(e)).ok())
+            .inspect(|state| anomalies.check_state(state)),
+    );
+    if let Some(e) = error {
+        println!("FATAL: could not parse metafile: {e}");
+        return ExitCode::FAILURE;
+    }
+    println!("Airports: {}", hashmaps.faa.len());
+    println!("Charts: {}", hashmaps.counts.total);
+    for (group, count) in &hashmaps.counts.groups {
+        println!("  {group:?}: {count}");
+    }
+    println!("States:");
+    for (state, count) in &hashmaps.counts.states {
+        println!("  {state}: {count}");
+    }
+    if hashmaps.counts.total < config.min_charts {
+        fatal.push(format!(
+            "{} charts loaded, fewer than the minimum of {}",
+            hashmaps.counts.total, config.min_charts
+        ));
+    }
+
+    for anomaly in &anomalies.found {
+        println!("WARNING: {anomaly}");
+    }
+    for error in &fatal {
+        println!("FATAL: {error}");
+    }
+    println!(
+        "Validation finished with {} warnings and {} fatal issues",
+        anomalies.found.len(),
+        fatal.len()
+    );
+
+    if fatal.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Returns fatal effective date issues. An unparseable end date is only an anomaly, as the
+/// server then assumes a standard cycle length, and so is an expiry within the grace period.
+fn check_effective_dates(
+    config: &Config,
+    dtpp: &MetafileHeader,
+    anomalies: &mut Vec<String>,
+) -> Vec<String> {
+    let mut fatal = Vec::new();
+    match (
+        parse_effective_date(&dtpp.from_effective_date),
+        parse_effective_date(&dtpp.to_effective_date),
+    ) {
+        (Ok(from), Ok(to)) if from >= to => fatal.push(format!(
+            "effective start {from} is not before effective end {to}"
+        )),
+        (Ok(from), Ok(to)) => {
+            println!("Effective: {from} to {to}");
+            let expired_for = (Utc::now() - to).to_std().unwrap_or_default();
+            if expired_for > config.expiry_grace {
+                fatal.push(format!("expired on {to}, beyond the grace period"));
+            } else if !expired_for.is_zero() {
+                anomalies.push(format!("expired on {to}, would be served as stale"));
+            }
+        }
+        (from, to) => {
+            if from.is_err() {
+                fatal.push(format!(
+                    "unparseable effective start date {:?}",
+                    dtpp.from_effective_date
+                ));
+            }
+            if to.is_err() {
+                anomalies.push(format!(
+                    "unparseable effective end date {:?}",
+                    dtpp.to_effective_date
+                ));
+            }
+        }
+    }
+    fatal
+}
+
+/// Anomalies found so far while streaming a metafile's states
+#[derive(Default)]
+struct Anomalies {
+    found: Vec<String>,
+    seen_records: HashSet<(String, String, String, String)>,
+}
+
+impl Anomalies {
+    fn check_state(&mut self, state: &State) {
+        if !state.has_known_code() {
+            self.found.push(format!(
+                "unknown state code {:?} for {:?}",
+                state.id, state.full_name
+            ));
+        }
+        for city in &state.cities {
+            for airport in &city.airports {
+                if airport.apt_ident.trim().is_empty() {
+                    self.found.push(format!(
+                        "blank FAA ident for airport {:?} in {}, {}",
+                        airport.id, city.id, state.id
+                    ));
+                }
+                if !airport.icao_ident.is_empty() && !is_plausible_icao(&airport.icao_ident) {
+                    self.found.push(format!(
+                        "suspicious ICAO ident {:?} for airport {}",
+                        airport.icao_ident, airport.apt_ident
+                    ));
+                }
+                for record in &airport.chart_records {
+                    if record.pdf_name.trim().is_empty() {
+                        self.found.push(format!(
+                            "no PDF name for record {:?} of airport {}, it will be skipped",
+                            record.chart_name, airport.apt_ident
+                        ));
+                    }
+                    if !self.seen_records.insert((
+                        airport.apt_ident.clone(),
+                        record.chart_name.clone(),
+                        record.pdf_name.clone(),
+                        record.useraction.clone(),
+                    )) {
+                        self.found.push(format!(
+                            "duplicate record {:?} ({}) for airport {}",
+                            record.chart_name, record.pdf_name, airport.apt_ident
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn is_plausible_icao(ident: &str) -> bool {
+    ident.len() == 4
+        && ident
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+}
+
+async fn read_metafile(config: &Config, source: &str) -> Result<String, anyhow::Error> {
+    let bytes = if source.starts_with("http://") || source.starts_with("https://") {
+        build_client(config.fetch_timeout)?
+            .get(source)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec()
+    } else {
+        tokio::fs::read(source).await?
+    };
+    Ok(decode_document(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeDelta};
+
+    fn config() -> Config {
+        Config::from_vars(|_| None)
+    }
+
+    fn header(from: &str, to: &str) -> MetafileHeader {
+        MetafileHeader {
+            cycle: "2410".to_string(),
+            from_effective_date: from.to_string(),
+            to_effective_date: to.to_string(),
+        }
+    }
+
+    fn effective_date(date: DateTime<Utc>) -> String {
+        date.format("%H%MZ  %m/%d/%y").to_string()
+    }
+
+    fn check(from: &str, to: &str) -> (Vec<String>, Vec<String>) {
+        let mut anomalies = Vec::new();
+        let fatal = check_effective_dates(&config(), &header(from, to), &mut anomalies);
+        (fatal, anomalies)
+    }
+
+    #[test]
+    fn start_not_before_end_is_fatal() {
+        for to in ["0901Z  10/31/24", "0901Z  10/03/24"] {
+            let (fatal, _) = check("0901Z  10/31/24", to);
+            assert_eq!(fatal.len(), 1, "{to}");
+            assert!(fatal[0].contains("is not before"), "{to}");
+        }
+    }
+
+    #[test]
+    fn expiry_is_fatal_only_beyond_the_grace_period() {
+        let now = Utc::now();
+        let (fatal, anomalies) = check(
+            &effective_date(now - TimeDelta::days(30)),
+            &effective_date(now - TimeDelta::days(2)),
+        );
+        assert!(fatal[0].contains("beyond the grace period"));
+        assert!(anomalies.is_empty());
+
+        let (fatal, anomalies) = check(
+            &effective_date(now - TimeDelta::days(28)),
+            &effective_date(now - TimeDelta::hours(2)),
+        );
+        assert!(fatal.is_empty());
+        assert!(anomalies[0].contains("would be served as stale"));
+
+        let (fatal, anomalies) = check(
+            &effective_date(now - TimeDelta::days(1)),
+            &effective_date(now + TimeDelta::days(27)),
+        );
+        assert!(fatal.is_empty());
+        assert!(anomalies.is_empty());
+    }
+
+    #[test]
+    fn unparseable_end_date_is_a_warning() {
+        let (fatal, anomalies) = check("0901Z  10/03/24", "end of cycle");
+        assert!(fatal.is_empty());
+        assert_eq!(
+            anomalies,
+            [r#"unparseable effective end date "end of cycle""#]
+        );
+
+        let (fatal, _) = check("start of cycle", "0901Z  10/31/24");
+        assert!(fatal[0].contains("unparseable effective start date"));
+    }
+
+    #[test]
+    fn state_anomalies_are_found() {
+        let record = |code: &str, name: &str, pdf: &str| {
+            format!(
+                "<record><chartseq>10100</chartseq><chart_code>{code}</chart_code>\
+                 <chart_name>{name}</chart_name><useraction/><pdf_name>{pdf}</pdf_name>\
+                 <cn_flg>N</cn_flg><cnsection/><cnpage/><bvsection>C</bvsection><bvpage/>\
+                 <procuid/><two_colored>N</two_colored><civil/><faanfd18/><copter/>\
+                 <amdtnum/><amdtdate/></record>"
+            )
+        };
+        let diagram = record("APD", "AIRPORT DIAGRAM", "00001AD.PDF");
+        let ils = record("IAP", "ILS RWY 4L", "00610IL4L.PDF");
+        let xml = format!(
+            r#"<digital_tpp cycle="2410" from_edate="0901Z  10/03/24" to_edate="0901Z  10/31/24">
+<state_code ID="NY" state_fullname="New York"><city_name ID="NEW YORK" volume="NE-2">
+<airport_name ID="NAMELESS" military="N" apt_ident=" " icao_ident="" alnum="1">
+{diagram}</airport_name>
+<airport_name ID="JOHN F KENNEDY INTL" military="N" apt_ident="JFK" icao_ident="kjfk" alnum="610">
+{ils}{ils}</airport_name>
+</city_name></state_code></digital_tpp>"#
+        );
+        let (states, _) = StateReader::new(&xml).unwrap();
+        let mut anomalies = Anomalies::default();
+        for state in states {
+            anomalies.check_state(&state.unwrap());
+        }
+        assert_eq!(
+            anomalies.found,
+            [
+                r#"blank FAA ident for airport "NAMELESS" in NEW YORK, NY"#,
+                r#"suspicious ICAO ident "kjfk" for airport JFK"#,
+                r#"duplicate record "ILS RWY 4L" (00610IL4L.PDF) for airport JFK"#,
+            ]
+        );
+    }
+}